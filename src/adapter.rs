@@ -0,0 +1,53 @@
+use crate::err::Res;
+use bluer::{Adapter, Session};
+use serde::Serialize;
+
+/// Name, address and power state of a bluetooth adapter.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct AdapterInfo {
+    pub name: String,
+    pub address: String,
+    pub powered: bool,
+}
+
+impl std::fmt::Display for AdapterInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // to_string really should not fail...
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+/// Print the available bluetooth adapters. See [`get_adapters`].
+#[tokio::main(flavor = "current_thread")]
+pub async fn print_adapters() -> Res<()> {
+    for info in get_adapters().await? {
+        println!("{}", info);
+    }
+    Ok(())
+}
+
+/// List the available bluetooth adapters.
+pub async fn get_adapters() -> Res<Vec<AdapterInfo>> {
+    let session = Session::new().await?;
+    let mut adapters = vec![];
+    for name in session.adapter_names().await? {
+        let adapter = session.adapter(&name)?;
+        adapters.push(AdapterInfo {
+            address: adapter.address().await?.to_string(),
+            powered: adapter.is_powered().await?,
+            name,
+        });
+    }
+    Ok(adapters)
+}
+
+/// Adapter with the given name (eg. `hci1`) or the default adapter if `None`,
+/// powered on.
+pub async fn open_adapter(session: &Session, name: Option<&str>) -> Res<Adapter> {
+    let adapter = match name {
+        Some(name) => session.adapter(name)?,
+        None => session.default_adapter().await?,
+    };
+    adapter.set_powered(true).await?;
+    Ok(adapter)
+}
@@ -1,10 +1,64 @@
-use crate::err::Res;
-use crate::ruuvi::Advertisement;
-use bluer::monitor::{data_type, Monitor, MonitorHandle, Pattern};
-use bluer::{Adapter, Device};
-use futures::StreamExt;
+use crate::adapter::open_adapter;
+use crate::calibration::Calibrations;
+use crate::err::{Error, Res};
+use crate::filter::DeviceFilter;
+use crate::presence::PresenceTracker;
+use crate::ruuvi::{Advertisement, Observation};
+use crate::stage;
+use crate::throttle::{Throttle, Throttler};
+use crate::units::Units;
+use bluer::monitor::{data_type, Monitor, MonitorEvent, MonitorHandle, MonitorManager, Pattern};
+use bluer::{
+    Adapter, AdapterEvent, Address, DeviceEvent, DeviceProperty, DiscoveryFilter,
+    DiscoveryTransport, Session,
+};
+use chrono::{DateTime, Utc};
+use futures::stream::BoxStream;
+use futures::{future, pin_mut, stream, Stream, StreamExt};
 use macaddr::MacAddr6;
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+use tokio_stream::StreamMap;
+
+const RUUVI_ID: u16 = 0x0499;
+
+/// Options for [`print_advertisements`] and [`scan`].
+#[derive(Clone, Debug)]
+pub struct ScanOptions {
+    /// Names of the bluetooth adapters to scan with, every adapter if empty.
+    pub adapters: Vec<String>,
+    /// Maximum time to wait for the advertisements from a list of devices.
+    pub timeout: Option<Duration>,
+    /// Number of advertisements printed from each device in a list.
+    pub readings: usize,
+    /// Devices silent for this many times their average advertisement
+    /// interval are reported offline.
+    pub offline_factor: f64,
+    /// Devices to scan, devices rejected by the filter are ignored.
+    pub filter: DeviceFilter,
+    /// Limits the rate of the observations from each device.
+    pub throttle: Option<Throttle>,
+    /// Corrections applied to the observations.
+    pub calibration: Calibrations,
+    /// Units of the printed observations.
+    pub units: Units,
+}
+
+impl Default for ScanOptions {
+    fn default() -> Self {
+        Self {
+            adapters: vec![],
+            timeout: Some(Duration::from_secs(60)),
+            readings: 1,
+            offline_factor: 10.0,
+            filter: DeviceFilter::default(),
+            throttle: None,
+            calibration: Calibrations::default(),
+            units: Units::default(),
+        }
+    }
+}
 
 fn manufacturer_pattern(manufacturer_id: u16) -> Monitor {
     Monitor {
@@ -18,60 +72,569 @@ fn manufacturer_pattern(manufacturer_id: u16) -> Monitor {
 }
 
 /// Listen to ble advertisements and print the everything with ruuvi
-/// manufacturer id (`0x0499`).
+/// manufacturer id (`0x0499`). See [`scan`].
 ///
-/// If `opt_macs` is not `None`, print advertisement from each device only once
-/// until all the listed devices have been observed.
+/// If `opt_macs` is not `None`, print `opts.readings` advertisements from each
+/// device until they have been printed from all the listed devices or
+/// `opts.timeout` has passed. In the latter case, an [`Error::NotFound`] listing
+/// the missing devices is returned after printing the observed advertisements.
 #[tokio::main(flavor = "current_thread")]
-pub async fn print_advertisements(opt_macs: Option<Vec<MacAddr6>>) -> Res<()> {
-    let id = 0x0499;
-    let session = bluer::Session::new().await?;
-    let adapter = session.default_adapter().await?;
-    let mm = adapter.monitor().await?;
-    adapter.set_powered(true).await?;
-    let mut mh = mm.register(manufacturer_pattern(id)).await?;
-
+pub async fn print_advertisements(opt_macs: Option<Vec<MacAddr6>>, opts: &ScanOptions) -> Res<()> {
+    let events = scan(opts).await?;
+    pin_mut!(events);
     match opt_macs {
-        Some(macs) => scan_cached(&mut mh, id, &adapter, macs.into_iter().collect()).await,
-        None => scan_everything(&mut mh, id, &adapter).await,
+        Some(macs) => print_latest(events, macs, opts).await,
+        None => {
+            while let Some(event) = events.next().await {
+                match event? {
+                    ScanEvent::Observation(obs) => println!("{}", opts.units.to_json(&obs)),
+                    ScanEvent::DecodeError(e) => eprintln!("{}", e),
+                    ScanEvent::Online(p) => eprintln!("{}: online ({})", p.mac, p.adapter),
+                    ScanEvent::Offline(p) => eprintln!("{}: offline ({})", p.mac, p.adapter),
+                }
+            }
+            Ok(())
+        }
     }
 }
 
-async fn scan_cached(
-    mh: &mut MonitorHandle,
-    id: u16,
-    adapter: &Adapter,
-    mut macs: HashSet<MacAddr6>,
+/// Event from [`scan`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScanEvent {
+    Observation(Observation),
+    DecodeError(DecodeError),
+    /// First advertisement from a device that was not online.
+    Online(Presence),
+    /// Device lost by all of the adapters that have seen it, or silent for
+    /// longer than `opts.offline_factor` times its advertisement interval.
+    Offline(Presence),
+}
+
+/// Device that came into or went out of range of an adapter.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Presence {
+    pub datetime: DateTime<Utc>,
+    pub mac: MacAddr6,
+    pub adapter: String,
+}
+
+/// Ruuvi manufacturer data that could not be decoded as an advertisement.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodeError {
+    pub datetime: DateTime<Utc>,
+    pub mac: MacAddr6,
+    pub adapter: String,
+    pub data: Vec<u8>,
+    pub error: String,
+    /// Number of decode errors from the device so far.
+    pub count: usize,
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let data: String = self.data.iter().map(|b| format!("{:02x}", b)).collect();
+        write!(
+            f,
+            "{} ({}): {} (data: {}, {} errors)",
+            self.mac, self.adapter, self.error, data, self.count
+        )
+    }
+}
+
+/// Advertisements with ruuvi manufacturer id received by the adapters in
+/// `opts.adapters` (every adapter if empty) from the devices accepted by
+/// `opts.filter`.
+///
+/// The streams from the adapters are merged, and an advertisement (mac and
+/// measurement sequence number) seen by several adapters is returned only once.
+/// Manufacturer data that cannot be decoded is returned as
+/// [`ScanEvent::DecodeError`] and the scanning continues, as it does when the
+/// properties of a device cannot be read (the error is printed on stderr).
+/// Devices coming into
+/// and going out of range are reported with [`ScanEvent::Online`] and
+/// [`ScanEvent::Offline`], see also [`PresenceTracker`]. The observations are
+/// calibrated with `opts.calibration` and finally throttled with
+/// `opts.throttle`.
+///
+/// Devices are found with the advertisement monitor api, which requires
+/// experimental features from `bluetoothd`. If the monitor cannot be registered,
+/// regular device discovery is used instead.
+pub async fn scan(opts: &ScanOptions) -> Res<impl Stream<Item = Res<ScanEvent>>> {
+    let session = Session::new().await?;
+    let names = match opts.adapters.is_empty() {
+        true => session.adapter_names().await?,
+        false => opts.adapters.clone(),
+    };
+    if names.is_empty() {
+        Err(Error::NotFound("no bluetooth adapters".to_string()))?
+    }
+    let mut scans = vec![];
+    for name in names {
+        let adapter = open_adapter(&session, Some(&name)).await?;
+        let scan = AdapterScan::new(adapter, RUUVI_ID, opts.filter.clone()).await?;
+        scans.push(scan.into_stream());
+    }
+    let mut merge = Merge::default();
+    let calibration = opts.calibration.clone();
+    let events = stream::select_all(scans).filter_map(move |res| {
+        future::ready(match res {
+            Ok(event) => merge.process(event).map(|e| Ok(calibration.event(e))),
+            Err(e) => Some(Err(e)),
+        })
+    });
+    let tracker = PresenceTracker::new(opts.offline_factor);
+    let events = Box::pin(stage::apply(events, tracker));
+    Ok(match opts.throttle.clone() {
+        Some(throttle) => stage::apply(events, Throttler::new(throttle)).left_stream(),
+        None => events.right_stream(),
+    })
+}
+
+async fn print_latest(
+    events: impl Stream<Item = Res<ScanEvent>> + Unpin,
+    macs: Vec<MacAddr6>,
+    opts: &ScanOptions,
 ) -> Res<()> {
-    while let Some(mevt) = mh.next().await {
-        if let Some((_, ruuvi)) = Advertisement::from_monitor_event(mevt, adapter, id).await? {
-            if macs.remove(&ruuvi.mac()) {
-                println!("{}", ruuvi);
+    let mut events = events;
+    let mut remaining = Remaining::new(macs, opts.readings);
+    let deadline = opts.timeout.map(|t| Instant::now() + t);
+    let timeout = async {
+        match deadline {
+            Some(d) => tokio::time::sleep_until(d).await,
+            None => future::pending().await,
+        }
+    };
+    pin_mut!(timeout);
+    while !remaining.is_empty() {
+        let event = tokio::select! {
+            event = events.next() => event.ok_or("unexpected end of events")??,
+            _ = &mut timeout => break,
+        };
+        let obs = match event {
+            ScanEvent::Observation(obs) => obs,
+            ScanEvent::DecodeError(e) => {
+                eprintln!("{}", e);
+                continue;
             }
-            if macs.is_empty() {
-                return Ok(());
+            _ => continue,
+        };
+        if remaining.take(obs.advertisement.mac()) {
+            println!("{}", opts.units.to_json(&obs));
+        }
+    }
+    remaining.check(opts.timeout)
+}
+
+/// Number of advertisements still to be printed from each device.
+struct Remaining(HashMap<MacAddr6, usize>);
+
+impl Remaining {
+    fn new(macs: Vec<MacAddr6>, readings: usize) -> Self {
+        // nothing is needed from any device with zero readings
+        let counts = macs.into_iter().map(|m| (m, readings));
+        Self(counts.filter(|(_, n)| *n > 0).collect())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Whether an advertisement from `mac` is still needed, counting it if so.
+    fn take(&mut self, mac: MacAddr6) -> bool {
+        let Some(n) = self.0.get_mut(&mac) else {
+            return false;
+        };
+        *n -= 1;
+        if *n == 0 {
+            self.0.remove(&mac);
+        }
+        true
+    }
+
+    /// [`Error::NotFound`] listing the devices with advertisements missing
+    /// after `timeout`, if any.
+    fn check(&self, timeout: Option<Duration>) -> Res<()> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let mut missing: Vec<_> = self.0.keys().map(|m| m.to_string()).collect();
+        missing.sort();
+        Err(Error::NotFound(format!(
+            "missing advertisements from {} after {} s",
+            missing.join(", "),
+            timeout.unwrap_or_default().as_secs()
+        )))
+    }
+}
+
+/// Source of the devices advertising ruuvi manufacturer data.
+enum Discovery {
+    /// Advertisement monitor, unregistered when the manager is dropped.
+    Monitor {
+        _mm: MonitorManager,
+        mh: MonitorHandle,
+    },
+    /// Regular device discovery, includes every (also previously) known device.
+    Devices(BoxStream<'static, AdapterEvent>),
+}
+
+impl Discovery {
+    async fn new(adapter: &Adapter, id: u16) -> Res<Self> {
+        match Self::monitor(adapter, id).await {
+            Ok(discovery) => Ok(discovery),
+            Err(e) => {
+                eprintln!(
+                    "{}: advertisement monitor unavailable ({}), using device discovery",
+                    adapter.name(),
+                    e
+                );
+                Self::devices(adapter).await
             }
+        }
+    }
+
+    async fn monitor(adapter: &Adapter, id: u16) -> Res<Self> {
+        let mm = adapter.monitor().await?;
+        let mh = mm.register(manufacturer_pattern(id)).await?;
+        Ok(Self::Monitor { _mm: mm, mh })
+    }
+
+    async fn devices(adapter: &Adapter) -> Res<Self> {
+        let filter = DiscoveryFilter {
+            transport: DiscoveryTransport::Le,
+            duplicate_data: true,
+            ..Default::default()
         };
+        adapter.set_discovery_filter(filter).await?;
+        Ok(Self::Devices(adapter.discover_devices().await?.boxed()))
+    }
+
+    /// Next found or lost device, `None` if the discovery has ended.
+    async fn next(&mut self) -> Option<DeviceChange> {
+        loop {
+            match self {
+                Self::Monitor { mh, .. } => match mh.next().await? {
+                    MonitorEvent::DeviceFound(d) => return Some(DeviceChange::Found(d.device)),
+                    MonitorEvent::DeviceLost(d) => return Some(DeviceChange::Lost(d.device)),
+                    _ => {}
+                },
+                Self::Devices(events) => match events.next().await? {
+                    AdapterEvent::DeviceAdded(addr) => return Some(DeviceChange::Found(addr)),
+                    AdapterEvent::DeviceRemoved(addr) => return Some(DeviceChange::Lost(addr)),
+                    _ => {}
+                },
+            }
+        }
     }
-    Err("unexpected end of events")?
 }
 
-async fn scan_everything(mh: &mut MonitorHandle, id: u16, adapter: &Adapter) -> Res<()> {
-    while let Some(mevt) = mh.next().await {
-        if let Some((dev, ruuvi)) = Advertisement::from_monitor_event(mevt, adapter, id).await? {
-            println!("{}", ruuvi);
-            tokio::spawn(scan_device_events(dev, id));
+enum DeviceChange {
+    Found(Address),
+    Lost(Address),
+}
+
+/// Advertisements received by a single adapter.
+struct AdapterScan {
+    adapter: Adapter,
+    id: u16,
+    filter: DeviceFilter,
+    discovery: Discovery,
+    // tracked devices, further advertisements are received as property changes
+    devices: StreamMap<Address, BoxStream<'static, DeviceEvent>>,
+    // tracked devices that have sent ruuvi advertisements
+    online: HashSet<Address>,
+    // tracked devices whose name is needed by the filter but not yet known
+    unnamed: HashSet<Address>,
+    rssi: HashMap<Address, i16>,
+    queue: VecDeque<ScanEvent>,
+}
+
+impl AdapterScan {
+    async fn new(adapter: Adapter, id: u16, filter: DeviceFilter) -> Res<Self> {
+        let discovery = Discovery::new(&adapter, id).await?;
+        Ok(Self {
+            adapter,
+            id,
+            filter,
+            discovery,
+            devices: StreamMap::new(),
+            online: HashSet::new(),
+            unnamed: HashSet::new(),
+            rssi: HashMap::new(),
+            queue: VecDeque::new(),
+        })
+    }
+
+    fn into_stream(self) -> BoxStream<'static, Res<ScanEvent>> {
+        stream::unfold(self, |mut scan| async {
+            scan.next().await.map(|res| (res, scan))
+        })
+        .boxed()
+    }
+
+    async fn next(&mut self) -> Option<Res<ScanEvent>> {
+        loop {
+            if let Some(event) = self.queue.pop_front() {
+                return Some(Ok(event));
+            }
+            tokio::select! {
+                change = self.discovery.next() => match change? {
+                    // eg. a device removed before its properties could be read,
+                    // only the discovery itself ending ends the scan
+                    DeviceChange::Found(addr) => {
+                        if let Err(e) = self.device_found(addr).await {
+                            let mac = MacAddr6::from(addr.0);
+                            eprintln!("{} ({}): {}", mac, self.adapter.name(), e);
+                        }
+                    }
+                    DeviceChange::Lost(addr) => self.device_lost(addr),
+                },
+                Some((addr, devt)) = self.devices.next() => self.device_event(addr, devt),
+            }
+        }
+    }
+
+    async fn device_found(&mut self, addr: Address) -> Res<()> {
+        let dev = self.adapter.device(addr)?;
+        if !self.filter.is_empty() {
+            let mac = MacAddr6::from(addr.0);
+            let name = match self.filter.uses_names() {
+                true => dev.name().await?,
+                false => None,
+            };
+            if !self.filter.accepts(mac, name.as_deref()) {
+                // eg. the name has not been resolved yet, decided once it is
+                if self.filter.awaits_name(mac, name.as_deref())
+                    && !self.devices.contains_key(&addr)
+                {
+                    self.unnamed.insert(addr);
+                    self.devices.insert(addr, dev.events().await?.boxed());
+                }
+                return Ok(());
+            }
+            self.unnamed.remove(&addr);
+        }
+        let man_data = dev.manufacturer_data().await?;
+        let data = man_data.and_then(|mut md| md.remove(&self.id));
+        // with device discovery, manufacturer data might only become available later
+        let discovering = matches!(self.discovery, Discovery::Devices(_));
+        if (data.is_some() || discovering) && !self.devices.contains_key(&addr) {
+            self.devices.insert(addr, dev.events().await?.boxed());
+        }
+        let Some(data) = data else { return Ok(()) };
+        match dev.rssi().await? {
+            Some(rssi) => _ = self.rssi.insert(addr, rssi),
+            // known devices that are not in range are included in the discovery
+            None if discovering => return Ok(()),
+            None => {}
+        }
+        self.decode(addr, data);
+        Ok(())
+    }
+
+    fn device_lost(&mut self, addr: Address) {
+        self.devices.remove(&addr);
+        self.unnamed.remove(&addr);
+        self.rssi.remove(&addr);
+        if self.online.remove(&addr) {
+            let presence = self.presence(addr);
+            self.queue.push_back(ScanEvent::Offline(presence));
+        }
+    }
+
+    fn device_event(&mut self, addr: Address, devt: DeviceEvent) {
+        if self.unnamed.contains(&addr) {
+            // the advertisements are dropped until the name is known
+            if let DeviceEvent::PropertyChanged(DeviceProperty::Name(name)) = devt {
+                self.unnamed.remove(&addr);
+                if !self.filter.accepts(MacAddr6::from(addr.0), Some(&name)) {
+                    self.devices.remove(&addr);
+                }
+            }
+            return;
+        }
+        match devt {
+            DeviceEvent::PropertyChanged(DeviceProperty::Rssi(rssi)) => {
+                self.rssi.insert(addr, rssi);
+            }
+            DeviceEvent::PropertyChanged(DeviceProperty::ManufacturerData(mut md)) => {
+                if let Some(data) = md.remove(&self.id) {
+                    self.decode(addr, data);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn decode(&mut self, addr: Address, data: Vec<u8>) {
+        if self.online.insert(addr) {
+            let presence = self.presence(addr);
+            self.queue.push_back(ScanEvent::Online(presence));
+        }
+        let Presence {
+            datetime,
+            mac,
+            adapter,
+        } = self.presence(addr);
+        let event = match Advertisement::from_rawv5(&data) {
+            Ok(advertisement) => ScanEvent::Observation(Observation {
+                datetime,
+                advertisement,
+                adapter,
+                rssi: self.rssi.get(&addr).copied(),
+            }),
+            Err(e) => ScanEvent::DecodeError(DecodeError {
+                datetime,
+                mac,
+                adapter,
+                data,
+                error: e.to_string(),
+                count: 0,
+            }),
         };
+        self.queue.push_back(event);
+    }
+
+    fn presence(&self, addr: Address) -> Presence {
+        Presence {
+            datetime: Utc::now(),
+            mac: MacAddr6::from(addr.0),
+            adapter: self.adapter.name().to_string(),
+        }
     }
-    Ok(())
 }
 
-async fn scan_device_events(dev: Device, id: u16) -> Res<()> {
-    let mut events = dev.events().await.map_err(|e| e.to_string())?;
-    while let Some(devt) = events.next().await {
-        if let Some(ruuvi) = Advertisement::from_device_event(devt, id)? {
-            println!("{}", ruuvi);
+const DEDUP_WINDOW: usize = 8;
+
+/// Combines the events from several adapters.
+#[derive(Debug, Default)]
+struct Merge {
+    // recently seen measurement sequence numbers
+    seen: HashMap<MacAddr6, VecDeque<u16>>,
+    errors: HashMap<MacAddr6, usize>,
+    // adapters the device is in range of
+    online: HashMap<MacAddr6, HashSet<String>>,
+}
+
+impl Merge {
+    /// The event if it is not a duplicate, with the error count updated.
+    fn process(&mut self, event: ScanEvent) -> Option<ScanEvent> {
+        match event {
+            ScanEvent::Observation(obs) => self
+                .is_new(&obs.advertisement)
+                .then_some(ScanEvent::Observation(obs)),
+            ScanEvent::DecodeError(mut e) => {
+                let count = self.errors.entry(e.mac).or_default();
+                *count += 1;
+                e.count = *count;
+                Some(ScanEvent::DecodeError(e))
+            }
+            ScanEvent::Online(p) => {
+                let adapters = self.online.entry(p.mac).or_default();
+                let first = adapters.is_empty();
+                adapters.insert(p.adapter.clone());
+                first.then_some(ScanEvent::Online(p))
+            }
+            ScanEvent::Offline(p) => {
+                let adapters = self.online.get_mut(&p.mac)?;
+                if !adapters.remove(&p.adapter) || !adapters.is_empty() {
+                    return None;
+                }
+                self.online.remove(&p.mac);
+                Some(ScanEvent::Offline(p))
+            }
+        }
+    }
+
+    /// Whether the advertisement has not been seen (recently) by any adapter.
+    fn is_new(&mut self, ruuvi: &Advertisement) -> bool {
+        let seen = self.seen.entry(ruuvi.mac()).or_default();
+        if seen.contains(&ruuvi.measurement) {
+            return false;
         }
+        if seen.len() == DEDUP_WINDOW {
+            seen.pop_front();
+        }
+        seen.push_back(ruuvi.measurement);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruuvi::test_advertisement;
+
+    #[test]
+    fn dedup_by_mac_and_measurement() {
+        let mut dedup = Merge::default();
+        assert!(dedup.is_new(&test_advertisement([1; 6], 1, 20.0)));
+        assert!(!dedup.is_new(&test_advertisement([1; 6], 1, 20.0)));
+        assert!(dedup.is_new(&test_advertisement([2; 6], 1, 20.0)));
+        assert!(dedup.is_new(&test_advertisement([1; 6], 2, 20.0)));
+        // late duplicate from another adapter
+        assert!(!dedup.is_new(&test_advertisement([1; 6], 1, 20.0)));
+        for i in 3..3 + DEDUP_WINDOW as u16 {
+            assert!(dedup.is_new(&test_advertisement([1; 6], i, 20.0)));
+        }
+        assert!(dedup.is_new(&test_advertisement([1; 6], 1, 20.0)));
+    }
+
+    #[test]
+    fn presence_over_adapters() {
+        let presence = |adapter: &str| Presence {
+            datetime: Utc::now(),
+            mac: MacAddr6::from([1; 6]),
+            adapter: String::from(adapter),
+        };
+        let mut merge = Merge::default();
+        assert!(merge.process(ScanEvent::Online(presence("hci0"))).is_some());
+        assert!(merge.process(ScanEvent::Online(presence("hci1"))).is_none());
+        assert!(merge
+            .process(ScanEvent::Offline(presence("hci0")))
+            .is_none());
+        assert!(merge
+            .process(ScanEvent::Offline(presence("hci1")))
+            .is_some());
+        assert!(merge
+            .process(ScanEvent::Offline(presence("hci1")))
+            .is_none());
+        assert!(merge.process(ScanEvent::Online(presence("hci1"))).is_some());
+    }
+
+    #[test]
+    fn decode_error_message() {
+        let e = DecodeError {
+            datetime: Utc::now(),
+            mac: MacAddr6::from([0xcb, 0xb8, 0x33, 0x4c, 0x88, 0x4f]),
+            adapter: String::from("hci0"),
+            data: vec![0x03, 0x29, 0x1a],
+            error: String::from("unsupported version"),
+            count: 2,
+        };
+        let msg_exp = "CB:B8:33:4C:88:4F (hci0): unsupported version (data: 03291a, 2 errors)";
+        assert_eq!(e.to_string(), msg_exp);
+    }
+
+    #[test]
+    fn remaining_readings() {
+        let mac1 = MacAddr6::from([1; 6]);
+        let mac2 = MacAddr6::from([2; 6]);
+        let mut remaining = Remaining::new(vec![mac1, mac2], 2);
+        assert!(remaining.take(mac1));
+        assert!(remaining.take(mac1));
+        assert!(!remaining.take(mac1));
+        assert!(!remaining.take(MacAddr6::from([3; 6])));
+        let err = remaining.check(Some(Duration::from_secs(30))).unwrap_err();
+        let msg_exp = "missing advertisements from 02:02:02:02:02:02 after 30 s";
+        assert!(matches!(&err, Error::NotFound(msg) if msg == msg_exp));
+        assert!(remaining.take(mac2));
+        assert!(remaining.take(mac2));
+        assert!(remaining.is_empty());
+        assert!(remaining.check(None).is_ok());
+        // nothing to wait for
+        let mut remaining = Remaining::new(vec![mac1], 0);
+        assert!(remaining.is_empty());
+        assert!(!remaining.take(mac1));
     }
-    Ok(())
 }
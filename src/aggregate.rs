@@ -0,0 +1,305 @@
+use crate::advertisements::{scan, ScanEvent, ScanOptions};
+use crate::calibration::Calibrations;
+use crate::err::Res;
+use crate::ruuvi::{ser_mac, Advertisement, Observation, Record, TaggedRecord};
+use crate::stage::{self, Stage};
+use crate::storage::Storage;
+use chrono::{DateTime, Utc};
+use futures::{pin_mut, Stream, StreamExt};
+use macaddr::MacAddr6;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// Summary of the advertisements from a device over a fixed interval.
+///
+/// Serialized like a [`TaggedRecord`] with the means of the measurements,
+/// followed by the minimums, maximums, the number of advertisements and the
+/// latest voltage.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Summary {
+    #[serde(serialize_with = "ser_mac")]
+    pub mac: MacAddr6,
+    /// Start of the interval and the means of the measurements.
+    #[serde(flatten)]
+    pub record: Record,
+    pub temperature_min: f64,
+    pub temperature_max: f64,
+    pub humidity_min: f64,
+    pub humidity_max: f64,
+    pub air_pressure_min: u32,
+    pub air_pressure_max: u32,
+    /// Number of advertisements within the interval.
+    pub count: usize,
+    /// Voltage from the latest advertisement.
+    pub voltage: f64,
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // to_string really should not fail...
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+impl From<Summary> for TaggedRecord {
+    fn from(value: Summary) -> Self {
+        Self {
+            mac: value.mac,
+            record: value.record,
+        }
+    }
+}
+
+impl Summary {
+    /// Summary of the advertisements (in the order they were received) within
+    /// the interval starting at `start`, `None` if there are none.
+    fn new(start: DateTime<Utc>, advertisements: &[Advertisement]) -> Option<Self> {
+        let last = advertisements.last()?;
+        let n = advertisements.len() as f64;
+        let values = |get: fn(&Advertisement) -> f64| advertisements.iter().map(get);
+        let mean = |get| values(get).sum::<f64>() / n;
+        let min = |get| values(get).fold(f64::INFINITY, f64::min);
+        let max = |get| values(get).fold(f64::NEG_INFINITY, f64::max);
+        let temperature = |a: &Advertisement| a.temperature;
+        let humidity = |a: &Advertisement| a.humidity;
+        let air_pressure = |a: &Advertisement| f64::from(a.air_pressure);
+        Some(Self {
+            mac: last.mac(),
+            record: Record {
+                datetime: start,
+                temperature: Some(mean(temperature)),
+                humidity: Some(mean(humidity)),
+                air_pressure: Some(mean(air_pressure).round() as u32),
+            },
+            temperature_min: min(temperature),
+            temperature_max: max(temperature),
+            humidity_min: min(humidity),
+            humidity_max: max(humidity),
+            air_pressure_min: min(air_pressure) as u32,
+            air_pressure_max: max(air_pressure) as u32,
+            count: advertisements.len(),
+            voltage: last.voltage,
+        })
+    }
+}
+
+/// Combines the observations from each device into a [`Summary`] per interval,
+/// see [`Stage`]. The intervals are aligned to the unix epoch (eg. 5 minute
+/// intervals start at :00, :05, ...) and a summary is returned once its
+/// interval has ended. Other events are dropped.
+#[derive(Debug)]
+pub struct Aggregator {
+    interval: chrono::Duration,
+    // start of the current interval and the advertisements within it
+    windows: HashMap<MacAddr6, (DateTime<Utc>, Vec<Advertisement>)>,
+}
+
+impl Aggregator {
+    /// Aggregator with the given interval, which must be at least 1ms.
+    pub fn new(interval: Duration) -> Res<Self> {
+        let interval = chrono::Duration::from_std(interval).map_err(|e| e.to_string())?;
+        if interval < chrono::Duration::milliseconds(1) {
+            Err("aggregation interval must be at least 1ms")?
+        }
+        Ok(Self {
+            interval,
+            windows: HashMap::new(),
+        })
+    }
+
+    fn interval_start(&self, datetime: DateTime<Utc>) -> DateTime<Utc> {
+        let ms = self.interval.num_milliseconds();
+        let start = datetime.timestamp_millis().div_euclid(ms) * ms;
+        datetime - chrono::Duration::milliseconds(datetime.timestamp_millis() - start)
+    }
+
+    fn flush(&mut self, mac: &MacAddr6) -> Option<Summary> {
+        let (start, advertisements) = self.windows.remove(mac)?;
+        Summary::new(start, &advertisements)
+    }
+}
+
+impl Stage for Aggregator {
+    type Output = Summary;
+
+    fn process(&mut self, event: ScanEvent) -> Vec<Summary> {
+        let ScanEvent::Observation(obs) = event else {
+            return vec![];
+        };
+        let start = self.interval_start(obs.datetime);
+        let mac = obs.advertisement.mac();
+        // the previous interval might not have expired yet
+        let summary = match self.windows.get(&mac) {
+            Some((prev, _)) if *prev != start => self.flush(&mac),
+            _ => None,
+        };
+        let window = self.windows.entry(mac).or_insert((start, vec![]));
+        window.1.push(obs.advertisement);
+        summary.into_iter().collect()
+    }
+
+    fn expire(&mut self, now: DateTime<Utc>) -> Vec<Summary> {
+        let mut ended: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|(_, (start, _))| *start + self.interval <= now)
+            .map(|(mac, (start, _))| (*start, *mac))
+            .collect();
+        ended.sort();
+        ended
+            .into_iter()
+            .filter_map(|(_, mac)| self.flush(&mac))
+            .collect()
+    }
+
+    fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        let start = self.windows.values().map(|(start, _)| *start).min()?;
+        Some(start + self.interval)
+    }
+}
+
+/// Summaries of advertisements received earlier (eg. from
+/// [`Storage::advertisements`]), ordered by the end of the interval.
+pub fn summarize(
+    interval: Duration,
+    advertisements: impl IntoIterator<Item = (DateTime<Utc>, Advertisement)>,
+) -> Res<Vec<Summary>> {
+    let mut aggregator = Aggregator::new(interval)?;
+    let mut summaries = vec![];
+    for (datetime, advertisement) in advertisements {
+        let obs = Observation {
+            datetime,
+            advertisement,
+            adapter: String::new(),
+            rssi: None,
+        };
+        summaries.extend(aggregator.process(ScanEvent::Observation(obs)));
+    }
+    summaries.extend(aggregator.expire(DateTime::<Utc>::MAX_UTC));
+    Ok(summaries)
+}
+
+/// Print the summaries of the advertisements, see [`aggregate`]. If `db` is
+/// given, the summaries are also stored, separately from the log records and
+/// without `opts.calibration`.
+#[tokio::main(flavor = "current_thread")]
+pub async fn print_summaries(
+    interval: Duration,
+    db: Option<impl AsRef<Path>>,
+    opts: &ScanOptions,
+) -> Res<()> {
+    let storage = db.map(Storage::open).transpose()?;
+    // calibrated only for printing
+    let raw_opts = ScanOptions {
+        calibration: Calibrations::default(),
+        ..opts.clone()
+    };
+    let summaries = aggregate(interval, &raw_opts).await?;
+    pin_mut!(summaries);
+    while let Some(summary) = summaries.next().await {
+        let summary = summary?;
+        if let Some(storage) = &storage {
+            storage.insert_summary(&summary)?;
+        }
+        let summary = opts.calibration.summary(summary);
+        println!("{}", opts.units.to_json(&summary));
+    }
+    Ok(())
+}
+
+/// Summaries of the advertisements from [`scan`] over fixed intervals, see
+/// [`Aggregator`].
+pub async fn aggregate(
+    interval: Duration,
+    opts: &ScanOptions,
+) -> Res<impl Stream<Item = Res<Summary>>> {
+    let aggregator = Aggregator::new(interval)?;
+    let events = Box::pin(scan(opts).await?);
+    Ok(stage::apply(events, aggregator))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruuvi::{test_advertisement, test_observation};
+
+    fn observation(secs: i64, temperature: f64, voltage: f64) -> ScanEvent {
+        let adv = Advertisement {
+            air_pressure: 100000 + secs as u32,
+            voltage,
+            ..test_advertisement([1; 6], secs as u16, temperature)
+        };
+        ScanEvent::Observation(test_observation(secs, adv))
+    }
+
+    #[test]
+    fn fixed_intervals() {
+        // 1_700_000_000 is 200s past a 5 minute boundary
+        let start = DateTime::<Utc>::from_timestamp(1_699_999_800, 0).unwrap();
+        let end = start + chrono::Duration::minutes(5);
+        let mut aggregator = Aggregator::new(Duration::from_secs(300)).unwrap();
+        for (secs, t, v) in [(0, 20.0, 3.0), (50, 23.0, 2.9), (90, 21.0, 2.8)] {
+            assert!(aggregator.process(observation(secs, t, v)).is_empty());
+        }
+        assert_eq!(aggregator.next_deadline(), Some(end));
+        assert!(aggregator
+            .expire(end - chrono::Duration::seconds(1))
+            .is_empty());
+        let summaries = aggregator.expire(end);
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.record.datetime, start);
+        assert_eq!(summary.record.temperature, Some(64.0 / 3.0));
+        assert_eq!(summary.record.air_pressure, Some(100047));
+        assert_eq!(summary.temperature_min, 20.0);
+        assert_eq!(summary.temperature_max, 23.0);
+        assert_eq!(summary.air_pressure_max, 100090);
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.voltage, 2.8);
+        assert!(aggregator.next_deadline().is_none());
+    }
+
+    #[test]
+    fn next_interval_flushes() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(300)).unwrap();
+        assert!(aggregator.process(observation(0, 20.0, 3.0)).is_empty());
+        let summaries = aggregator.process(observation(300, 22.0, 3.0));
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(summaries[0].count, 1);
+        assert_eq!(aggregator.windows.len(), 1);
+        assert!(Aggregator::new(Duration::ZERO).is_err());
+    }
+
+    #[test]
+    fn summarize_stored() {
+        let advertisements =
+            [0, 50, 300, 350, 400].map(|secs| match observation(secs, 20.0, 3.0) {
+                ScanEvent::Observation(obs) => (obs.datetime, obs.advertisement),
+                _ => unreachable!(),
+            });
+        let summaries = summarize(Duration::from_secs(300), advertisements).unwrap();
+        let counts: Vec<_> = summaries.iter().map(|s| s.count).collect();
+        assert_eq!(counts, vec![2, 2, 1]);
+    }
+
+    #[test]
+    fn summary_json() {
+        let start = DateTime::<Utc>::from_timestamp(1_699_999_800, 0).unwrap();
+        let ScanEvent::Observation(obs) = observation(0, 20.0, 3.0) else {
+            unreachable!()
+        };
+        let summary = Summary::new(start, &[obs.advertisement]).unwrap();
+        let exp = concat!(
+            r#"{"mac":"01:01:01:01:01:01","datetime":"2023-11-14T22:10:00+00:00","#,
+            r#""temperature":20.0,"humidity":40.0,"air_pressure":100000,"#,
+            r#""temperature_min":20.0,"temperature_max":20.0,"humidity_min":40.0,"#,
+            r#""humidity_max":40.0,"air_pressure_min":100000,"air_pressure_max":100000,"#,
+            r#""count":1,"voltage":3.0}"#
+        );
+        assert_eq!(summary.to_string(), exp);
+        let record = TaggedRecord::from(summary);
+        assert_eq!(record.record.datetime, start);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::advertisements::ScanEvent;
+use crate::aggregate::Summary;
+use crate::ruuvi::{Advertisement, Measurement, Record};
+use macaddr::MacAddr6;
+use serde::{de, Deserialize, Deserializer};
+use std::collections::HashMap;
+
+/// Linear correction `scale * value + offset`.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Correction {
+    pub offset: f64,
+    pub scale: f64,
+}
+
+impl Default for Correction {
+    fn default() -> Self {
+        Self {
+            offset: 0.0,
+            scale: 1.0,
+        }
+    }
+}
+
+impl Correction {
+    pub fn apply(&self, value: f64) -> f64 {
+        self.scale * value + self.offset
+    }
+
+    // air pressure is in whole pascals
+    fn apply_pa(&self, value: u32) -> u32 {
+        self.apply(value.into()).round() as u32
+    }
+}
+
+/// Corrections for the measurements of a single device.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Calibration {
+    pub temperature: Correction,
+    pub humidity: Correction,
+    pub air_pressure: Correction,
+}
+
+/// Calibrations of the devices by mac address. Measurements from devices
+/// without a calibration are left as is.
+///
+/// Deserialized from a table with mac addresses as keys, eg. in toml
+/// ```toml
+/// ["AB:CD:EF:12:34:56"]
+/// temperature = { offset = -0.3 }
+/// humidity = { offset = 2.5, scale = 1.02 }
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Calibrations(pub HashMap<MacAddr6, Calibration>);
+
+impl<'de> Deserialize<'de> for Calibrations {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        let calibrations: HashMap<String, Calibration> = HashMap::deserialize(d)?;
+        calibrations
+            .into_iter()
+            .map(|(mac, c)| Ok((mac.parse().map_err(de::Error::custom)?, c)))
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Calibrations {
+    /// Whether no device is calibrated.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Calibrated measurements of the advertisement.
+    pub fn advertisement(&self, mut adv: Advertisement) -> Advertisement {
+        if let Some(c) = self.0.get(&adv.mac()) {
+            adv.temperature = c.temperature.apply(adv.temperature);
+            adv.humidity = c.humidity.apply(adv.humidity);
+            adv.air_pressure = c.air_pressure.apply_pa(adv.air_pressure);
+        }
+        adv
+    }
+
+    /// Calibrated record from device `mac`, missing measurements stay missing.
+    pub fn record(&self, mac: MacAddr6, mut record: Record) -> Record {
+        if let Some(c) = self.0.get(&mac) {
+            record.temperature = record.temperature.map(|t| c.temperature.apply(t));
+            record.humidity = record.humidity.map(|h| c.humidity.apply(h));
+            record.air_pressure = record.air_pressure.map(|a| c.air_pressure.apply_pa(a));
+        }
+        record
+    }
+
+    /// Calibrated log measurement from device `mac`.
+    pub fn measurement(&self, mac: MacAddr6, measurement: Measurement) -> Measurement {
+        let Some(c) = self.0.get(&mac) else {
+            return measurement;
+        };
+        match measurement {
+            Measurement::Temp(ts, t) => Measurement::Temp(ts, c.temperature.apply(t)),
+            Measurement::Hum(ts, h) => Measurement::Hum(ts, c.humidity.apply(h)),
+            Measurement::AirPres(ts, a) => Measurement::AirPres(ts, c.air_pressure.apply_pa(a)),
+            Measurement::EndOfMeasurements => Measurement::EndOfMeasurements,
+        }
+    }
+
+    /// Calibrated summary, the means, minimums and maximums are corrected like
+    /// the measurements.
+    pub fn summary(&self, mut summary: Summary) -> Summary {
+        let Some(c) = self.0.get(&summary.mac) else {
+            return summary;
+        };
+        summary.record = self.record(summary.mac, summary.record);
+        let range = |corr: &Correction, min: f64, max: f64| {
+            let (a, b) = (corr.apply(min), corr.apply(max));
+            // negative scale swaps the extremes
+            (a.min(b), a.max(b))
+        };
+        (summary.temperature_min, summary.temperature_max) = range(
+            &c.temperature,
+            summary.temperature_min,
+            summary.temperature_max,
+        );
+        (summary.humidity_min, summary.humidity_max) =
+            range(&c.humidity, summary.humidity_min, summary.humidity_max);
+        let (a, b) = (
+            c.air_pressure.apply_pa(summary.air_pressure_min),
+            c.air_pressure.apply_pa(summary.air_pressure_max),
+        );
+        (summary.air_pressure_min, summary.air_pressure_max) = (a.min(b), a.max(b));
+        summary
+    }
+
+    /// Calibrate the observations, other events are returned as is.
+    pub fn event(&self, event: ScanEvent) -> ScanEvent {
+        match event {
+            ScanEvent::Observation(mut obs) => {
+                obs.advertisement = self.advertisement(obs.advertisement);
+                ScanEvent::Observation(obs)
+            }
+            event => event,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    fn calibrations() -> Calibrations {
+        let contents = r#"
+            ["01:01:01:01:01:01"]
+            temperature = { offset = -0.5 }
+            humidity = { offset = 2.0, scale = 1.5 }
+            air_pressure = { offset = 100.4 }
+        "#;
+        toml::from_str(contents).unwrap()
+    }
+
+    #[test]
+    fn calibrate() {
+        let calibrations = calibrations();
+        let dt = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let record = Record {
+            datetime: dt,
+            temperature: Some(20.0),
+            humidity: None,
+            air_pressure: Some(100000),
+        };
+        let exp = Record {
+            temperature: Some(19.5),
+            air_pressure: Some(100100),
+            ..record.clone()
+        };
+        let mac = MacAddr6::from([1; 6]);
+        assert_eq!(calibrations.record(mac, record.clone()), exp);
+        let other = MacAddr6::from([2; 6]);
+        assert_eq!(calibrations.record(other, record.clone()), record);
+        let hum = calibrations.measurement(mac, Measurement::Hum(dt, 40.0));
+        assert_eq!(hum, Measurement::Hum(dt, 62.0));
+    }
+
+    #[test]
+    fn calibrate_summary() {
+        let mut calibrations = calibrations();
+        let mac = MacAddr6::from([1; 6]);
+        calibrations.0.get_mut(&mac).unwrap().temperature.scale = -1.0;
+        let summary = Summary {
+            mac,
+            record: Record {
+                datetime: DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap(),
+                temperature: Some(20.0),
+                humidity: Some(40.0),
+                air_pressure: Some(100000),
+            },
+            temperature_min: 19.0,
+            temperature_max: 21.0,
+            humidity_min: 38.0,
+            humidity_max: 42.0,
+            air_pressure_min: 99900,
+            air_pressure_max: 100100,
+            count: 10,
+            voltage: 3.0,
+        };
+        let calibrated = calibrations.summary(summary);
+        assert_eq!(calibrated.record.temperature, Some(-20.5));
+        assert_eq!(calibrated.temperature_min, -21.5);
+        assert_eq!(calibrated.temperature_max, -19.5);
+        assert_eq!(calibrated.humidity_max, 65.0);
+        assert_eq!(calibrated.air_pressure_min, 100000);
+    }
+
+    #[test]
+    fn invalid_config() {
+        assert!(toml::from_str::<Calibrations>("[invalid]\ntemperature = {}").is_err());
+        let unknown = "[\"01:01:01:01:01:01\"]\ntemperature = { shift = 1 }";
+        assert!(toml::from_str::<Calibrations>(unknown).is_err());
+    }
+}
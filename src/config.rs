@@ -1,43 +1,432 @@
+use chrono::{DateTime, Utc};
+use clap::{Args, Parser, Subcommand};
 use macaddr::MacAddr6;
 use ruuvi::err::Res;
-use std::env::Args;
+use ruuvi::{
+    AccelerationUnit, Calibrations, Deltas, DeviceFilter, LogOptions, PressureUnit, Reduce,
+    ScanOptions, Sensor, TemperatureUnit, Throttle, Units,
+};
+use serde::Deserialize;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 #[derive(Debug)]
 pub enum Config {
-    Latest(Vec<MacAddr6>),
-    Log(MacAddr6, u8),
-    Scan,
+    Adapters,
+    Aggregate(Duration, Option<PathBuf>, ScanOptions),
+    Devices(ScanOptions),
+    Latest(Vec<MacAddr6>, ScanOptions),
+    Live(MacAddr6, LogOptions),
+    Log(Vec<MacAddr6>, DateTime<Utc>, LogOptions),
+    Scan(ScanOptions),
+    Sync(PathBuf, Vec<MacAddr6>, bool, LogOptions),
 }
 
-impl Config {
-    pub fn new(mut args: Args) -> Res<Config> {
-        let progname = args.next().ok_or("arguments missing")?;
-        match args.next().as_deref() {
-            Some("--latest") => Self::latest_config(args),
-            Some("--log") => Self::log_config(args, &progname),
-            Some(_) => Err(get_usage(&progname))?,
-            None => Ok(Self::Scan),
+/// Communicate with ruuvitags via bluetooth.
+///
+/// Durations are given as a number followed by a unit (s, m, h or d), eg. 90m,
+/// a plain number is in seconds. Times are either RFC 3339 timestamps or
+/// durations before the current time.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Cli {
+    /// Name of the bluetooth adapter, can be given multiple times when scanning
+    #[arg(long = "adapter", value_name = "NAME", global = true)]
+    adapters: Vec<String>,
+    /// Configuration file (toml)
+    #[arg(long, value_name = "FILE", global = true)]
+    config: Option<PathBuf>,
+    /// Ignore the calibrations in the configuration file
+    #[arg(long, global = true)]
+    raw: bool,
+    /// Temperature unit of the output (c, f or k)
+    #[arg(long, default_value = "c", value_name = "UNIT", global = true)]
+    temperature_unit: TemperatureUnit,
+    /// Air pressure unit of the output (pa, hpa, inhg or mmhg)
+    #[arg(long, default_value = "pa", value_name = "UNIT", global = true)]
+    pressure_unit: PressureUnit,
+    /// Acceleration unit of the output (g or m/s2)
+    #[arg(long, default_value = "g", value_name = "UNIT", global = true)]
+    acceleration_unit: AccelerationUnit,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print rawv5 advertisements indefinitely (the default)
+    Scan {
+        #[command(flatten)]
+        filter: FilterArgs,
+        #[command(flatten)]
+        throttle: ThrottleArgs,
+    },
+    /// Print summaries (means, minimums and maximums) of the advertisements
+    /// from each device over fixed intervals
+    Aggregate {
+        /// Length of the intervals, eg. 5m intervals start at :00, :05, ...
+        #[arg(long, default_value = "5m", value_parser = parse_duration)]
+        interval: Duration,
+        /// Also store the summaries (uncalibrated) in the sqlite database
+        #[arg(long, value_name = "FILE")]
+        db: Option<PathBuf>,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Print the first advertisements from each of the devices
+    Latest {
+        /// Give up after this long, exiting with status 2
+        #[arg(long, default_value = "60s", value_parser = parse_duration)]
+        timeout: Duration,
+        /// Number of advertisements printed from each device
+        #[arg(long, default_value = "1")]
+        readings: NonZeroUsize,
+        #[arg(required = true)]
+        macs: Vec<MacAddr6>,
+    },
+    /// Scan for a while and list the devices seen
+    Devices {
+        /// Duration of the scan
+        #[arg(long, default_value = "60s", value_parser = parse_duration)]
+        timeout: Duration,
+        /// Devices silent for this many times their advertisement interval are offline
+        #[arg(long, default_value_t = 10.0)]
+        offline_factor: f64,
+        #[command(flatten)]
+        filter: FilterArgs,
+    },
+    /// Connect to the device and print the rawv5 heartbeats it sends
+    Live {
+        mac: MacAddr6,
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    },
+    /// Print the log records from the devices, followed by a summary on stderr
+    Log {
+        /// Start of the log, the devices keep at most 10 days of history
+        #[arg(long, default_value = "10d", value_parser = parse_time)]
+        since: DateTime<Utc>,
+        /// End of the log, later records are dropped after they are received
+        #[arg(long, value_parser = parse_time)]
+        until: Option<DateTime<Utc>>,
+        /// Download only the log of the sensor (temperature, humidity or air_pressure),
+        /// can be given multiple times
+        #[arg(long = "sensor", value_name = "SENSOR")]
+        sensors: Vec<Sensor>,
+        /// Maximum time to download a log (from one connection), no limit by default
+        #[arg(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        #[command(flatten)]
+        connection: ConnectionArgs,
+        #[arg(required = true)]
+        macs: Vec<MacAddr6>,
+    },
+    /// Store the log records not yet in the sqlite database
+    Sync {
+        /// Clear the log from the devices once it has been stored
+        #[arg(long)]
+        clear_log: bool,
+        /// Maximum time to download a log (from one connection), no limit by default
+        #[arg(long, value_parser = parse_duration)]
+        timeout: Option<Duration>,
+        #[command(flatten)]
+        connection: ConnectionArgs,
+        db: PathBuf,
+        #[arg(required = true)]
+        macs: Vec<MacAddr6>,
+    },
+    /// List the bluetooth adapters
+    Adapters,
+}
+
+#[derive(Debug, Default, Args)]
+struct FilterArgs {
+    /// Only scan the device, can be given multiple times
+    #[arg(long = "allow", value_name = "MAC")]
+    allow: Vec<MacAddr6>,
+    /// Ignore the device, can be given multiple times
+    #[arg(long = "deny", value_name = "MAC")]
+    deny: Vec<MacAddr6>,
+}
+
+impl FilterArgs {
+    /// The filter from the configuration file extended with the arguments.
+    fn extend(self, filter: &DeviceFilter) -> DeviceFilter {
+        let mut filter = filter.clone();
+        filter.allow.extend(self.allow);
+        filter.deny.extend(self.deny);
+        filter
+    }
+}
+
+#[derive(Debug, Default, Args)]
+struct ThrottleArgs {
+    /// Print at most one observation per device per interval
+    #[arg(long, value_name = "DURATION", value_parser = parse_duration)]
+    throttle: Option<Duration>,
+    /// How the observation is chosen within the interval (latest, mean or median)
+    #[arg(long, default_value = "latest", requires = "throttle")]
+    reduce: Reduce,
+    /// Print only the observations that differ enough from the previous one
+    /// printed, eg. temperature=0.5,humidity=2 (fields: temperature, humidity,
+    /// air_pressure and voltage, always in °C, %, Pa and V)
+    #[arg(long, value_name = "DELTAS", conflicts_with = "throttle")]
+    on_change: Option<Deltas>,
+}
+
+impl ThrottleArgs {
+    fn throttle(self) -> Option<Throttle> {
+        match (self.throttle, self.on_change) {
+            (Some(interval), _) => Some(Throttle::Interval(interval, self.reduce)),
+            (None, Some(deltas)) => Some(Throttle::OnChange(deltas)),
+            (None, None) => None,
         }
     }
+}
+
+/// Contents of the configuration file.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    /// Devices to scan.
+    filter: DeviceFilter,
+    /// Corrections to the measurements of each device.
+    calibration: Calibrations,
+}
+
+impl FileConfig {
+    fn read(path: &Path) -> Res<Self> {
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| format!("unable to read {}: {}", path.display(), e))?;
+        let config = toml::from_str(&contents)
+            .map_err(|e| format!("invalid config {}: {}", path.display(), e))?;
+        Ok(config)
+    }
+}
+
+#[derive(Debug, Args)]
+struct ConnectionArgs {
+    /// Maximum time to look for the devices
+    #[arg(long, default_value = "60s", value_parser = parse_duration)]
+    discovery_timeout: Duration,
+    /// Maximum time to wait for data from a device
+    #[arg(long, default_value = "30s", value_parser = parse_duration)]
+    idle_timeout: Duration,
+}
 
-    fn latest_config(args: Args) -> Res<Self> {
-        args.into_iter()
-            .map(|s| Ok(s.parse()?))
-            .collect::<Res<Vec<_>>>()
-            .map(Self::Latest)
+impl Config {
+    pub fn new(args: std::env::Args) -> Res<Config> {
+        let cli = match Cli::try_parse_from(args) {
+            Ok(cli) => cli,
+            // help and version
+            Err(e) if !e.use_stderr() => e.exit(),
+            // status 2 is reserved for devices that were not found
+            Err(e) => Err(e.to_string().trim_end())?,
+        };
+        let file_config = match &cli.config {
+            Some(path) => FileConfig::read(path)?,
+            None => FileConfig::default(),
+        };
+        let calibration = match cli.raw {
+            true => Calibrations::default(),
+            false => file_config.calibration,
+        };
+        let units = Units {
+            temperature: cli.temperature_unit,
+            air_pressure: cli.pressure_unit,
+            acceleration: cli.acceleration_unit,
+        };
+        let scan_opts = ScanOptions {
+            adapters: cli.adapters.clone(),
+            filter: file_config.filter,
+            calibration: calibration.clone(),
+            units,
+            ..Default::default()
+        };
+        let log_opts = |connection: ConnectionArgs| -> Res<LogOptions> {
+            let adapter = match cli.adapters.as_slice() {
+                [] => None,
+                [adapter] => Some(adapter.clone()),
+                _ => Err("multiple adapters are only supported when scanning")?,
+            };
+            Ok(LogOptions {
+                adapter,
+                discovery_timeout: Some(connection.discovery_timeout),
+                idle_timeout: Some(connection.idle_timeout),
+                calibration: calibration.clone(),
+                units,
+                ..Default::default()
+            })
+        };
+        let default_command = Command::Scan {
+            filter: FilterArgs::default(),
+            throttle: ThrottleArgs::default(),
+        };
+        let config = match cli.command.unwrap_or(default_command) {
+            Command::Scan { filter, throttle } => Self::Scan(ScanOptions {
+                filter: filter.extend(&scan_opts.filter),
+                throttle: throttle.throttle(),
+                ..scan_opts
+            }),
+            Command::Aggregate {
+                interval,
+                db,
+                filter,
+            } => {
+                let opts = ScanOptions {
+                    filter: filter.extend(&scan_opts.filter),
+                    ..scan_opts
+                };
+                Self::Aggregate(interval, db, opts)
+            }
+            Command::Latest {
+                timeout,
+                readings,
+                macs,
+            } => Self::Latest(
+                macs,
+                ScanOptions {
+                    timeout: Some(timeout),
+                    readings: readings.get(),
+                    ..scan_opts
+                },
+            ),
+            Command::Devices {
+                timeout,
+                offline_factor,
+                filter,
+            } => Self::Devices(ScanOptions {
+                timeout: Some(timeout),
+                offline_factor,
+                filter: filter.extend(&scan_opts.filter),
+                ..scan_opts
+            }),
+            Command::Live { mac, connection } => Self::Live(mac, log_opts(connection)?),
+            Command::Log {
+                since,
+                until,
+                sensors,
+                timeout,
+                connection,
+                macs,
+            } => {
+                let opts = LogOptions {
+                    sensors,
+                    until,
+                    timeout,
+                    ..log_opts(connection)?
+                };
+                Self::Log(macs, since, opts)
+            }
+            Command::Sync {
+                clear_log,
+                timeout,
+                connection,
+                db,
+                macs,
+            } => {
+                let opts = LogOptions {
+                    timeout,
+                    ..log_opts(connection)?
+                };
+                Self::Sync(db, macs, clear_log, opts)
+            }
+            Command::Adapters => Self::Adapters,
+        };
+        Ok(config)
     }
+}
+
+/// Duration such as `90m` or `3d`, plain numbers are seconds.
+fn parse_duration(s: &str) -> Res<Duration> {
+    let (n, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let n: u64 = n.parse()?;
+    let unit_secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => Err(format!("invalid duration unit '{}'", unit))?,
+    };
+    let secs = n.checked_mul(unit_secs).ok_or("duration is too long")?;
+    Ok(Duration::from_secs(secs))
+}
 
-    fn log_config(mut args: Args, progname: &str) -> Res<Self> {
-        Ok(Config::Log(
-            args.next().ok_or(get_usage(progname))?.parse()?,
-            args.next().ok_or(get_usage(progname))?.parse()?,
-        ))
+/// RFC 3339 timestamp or a duration before the current time.
+fn parse_time(s: &str) -> Res<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(dt.with_timezone(&Utc));
     }
+    let duration = parse_duration(s).map_err(|_| format!("invalid time '{}'", s))?;
+    let duration = chrono::Duration::from_std(duration).map_err(|e| e.to_string())?;
+    Ok(Utc::now() - duration)
 }
 
-fn get_usage(program_name: &str) -> String {
-    format!(
-        "usage: {} [--log mac n_hours | --latest mac1 mac2 ...]",
-        program_name
-    )
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("90m").unwrap(), Duration::from_secs(5400));
+        assert_eq!(parse_duration("3d").unwrap(), Duration::from_secs(259200));
+        assert!(parse_duration("3w").is_err());
+        assert!(parse_duration("m").is_err());
+    }
+
+    #[test]
+    fn times() {
+        let dt = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        assert_eq!(parse_time("2023-11-15T00:13:20+02:00").unwrap(), dt);
+        let hour_ago = Utc::now() - chrono::Duration::hours(1);
+        let diff = parse_time("1h").unwrap() - hour_ago;
+        assert!(diff.num_seconds().abs() <= 1);
+        assert!(parse_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn file_config() {
+        let contents = r#"
+            [filter]
+            allow = ["CB:B8:33:4C:88:4F"]
+            names = ["Ruuvi *"]
+        "#;
+        let config: FileConfig = toml::from_str(contents).unwrap();
+        let filter_exp = DeviceFilter {
+            allow: vec![MacAddr6::from([0xcb, 0xb8, 0x33, 0x4c, 0x88, 0x4f])],
+            deny: vec![],
+            names: vec![String::from("Ruuvi *")],
+        };
+        assert_eq!(config.filter, filter_exp);
+        assert!(toml::from_str::<FileConfig>("[filter]\nallowed = []").is_err());
+        let contents = r#"
+            [calibration."CB:B8:33:4C:88:4F"]
+            temperature = { offset = -0.3 }
+        "#;
+        let config: FileConfig = toml::from_str(contents).unwrap();
+        let mac = MacAddr6::from([0xcb, 0xb8, 0x33, 0x4c, 0x88, 0x4f]);
+        assert_eq!(config.calibration.0[&mac].temperature.offset, -0.3);
+    }
+
+    #[test]
+    fn cli() {
+        use clap::CommandFactory;
+        Cli::command().debug_assert();
+        let args = ["ruuvi", "latest", "--readings", "0", "CB:B8:33:4C:88:4F"];
+        assert!(Cli::try_parse_from(args).is_err());
+        let args = [
+            "ruuvi",
+            "sync",
+            "--timeout",
+            "5m",
+            "ruuvi.db",
+            "CB:B8:33:4C:88:4F",
+        ];
+        let Some(Command::Sync { timeout, .. }) = Cli::try_parse_from(args).unwrap().command else {
+            panic!("not a sync command")
+        };
+        assert_eq!(timeout, Some(Duration::from_secs(300)));
+    }
 }
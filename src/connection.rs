@@ -0,0 +1,86 @@
+use crate::err::Res;
+use bluer::{Device, DeviceEvent, DeviceProperty};
+use futures::{future, Stream, StreamExt};
+use std::time::Duration;
+
+/// Exponential backoff between connection attempts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Backoff {
+    pub max_tries: u8,
+    /// Delay after the first failed attempt.
+    pub initial_delay: Duration,
+    /// Upper limit for the delay.
+    pub max_delay: Duration,
+    /// Multiplier of the delay after each failed attempt.
+    pub factor: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            max_tries: 5,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+            factor: 2,
+        }
+    }
+}
+
+impl Backoff {
+    /// Delay after the failed attempt number `attempt` (starting from 0).
+    pub fn delay(&self, attempt: u8) -> Duration {
+        let factor = self.factor.saturating_pow(attempt.into());
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+/// Connect to `device` unless already connected, retrying according to
+/// `backoff`. The error lists the reason of each failed attempt.
+pub async fn connect(device: &Device, backoff: &Backoff) -> Res<()> {
+    if device.is_connected().await? {
+        return Ok(());
+    }
+    let mut errors = vec![];
+    for attempt in 0..backoff.max_tries {
+        if attempt > 0 {
+            tokio::time::sleep(backoff.delay(attempt - 1)).await;
+        }
+        match device.connect().await {
+            Ok(()) => return Ok(()),
+            Err(e) => errors.push(format!("attempt {}: {}", attempt + 1, e)),
+        }
+    }
+    Err(format!(
+        "unable to connect device {} after {} attempts ({})",
+        device.address(),
+        backoff.max_tries,
+        errors.join(", ")
+    ))?
+}
+
+/// Stream that yields each time `device` disconnects.
+pub async fn disconnections(device: &Device) -> Res<impl Stream<Item = ()>> {
+    let events = device.events().await?;
+    Ok(events.filter_map(|evt| {
+        future::ready(match evt {
+            DeviceEvent::PropertyChanged(DeviceProperty::Connected(false)) => Some(()),
+            _ => None,
+        })
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_delay() {
+        let backoff = Backoff::default();
+        assert_eq!(backoff.delay(0), Duration::from_secs(1));
+        assert_eq!(backoff.delay(3), Duration::from_secs(8));
+        assert_eq!(backoff.delay(5), Duration::from_secs(30));
+        assert_eq!(backoff.delay(200), Duration::from_secs(30));
+    }
+}
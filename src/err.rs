@@ -3,8 +3,12 @@ use std::{error, fmt, num};
 #[derive(Debug)]
 pub enum Error {
     Bluer(bluer::Error),
+    Disconnected(String),
+    NotFound(String),
     Other(String),
     Parse(String),
+    Sqlite(rusqlite::Error),
+    Timeout(String),
 }
 
 pub type Res<T> = Result<T, Error>;
@@ -13,8 +17,12 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Error::Bluer(e) => write!(f, "{}", e),
+            Error::Disconnected(e) => write!(f, "{}", e),
+            Error::NotFound(e) => write!(f, "{}", e),
             Error::Other(e) => write!(f, "{}", e),
             Error::Parse(e) => write!(f, "{}", e),
+            Error::Sqlite(e) => write!(f, "{}", e),
+            Error::Timeout(e) => write!(f, "{}", e),
         }
     }
 }
@@ -33,6 +41,12 @@ impl From<macaddr::ParseError> for Error {
     }
 }
 
+impl From<num::ParseFloatError> for Error {
+    fn from(value: num::ParseFloatError) -> Self {
+        Self::Parse(value.to_string())
+    }
+}
+
 impl From<num::ParseIntError> for Error {
     fn from(value: num::ParseIntError) -> Self {
         Self::Parse(value.to_string())
@@ -45,6 +59,12 @@ impl From<num::TryFromIntError> for Error {
     }
 }
 
+impl From<rusqlite::Error> for Error {
+    fn from(value: rusqlite::Error) -> Self {
+        Self::Sqlite(value)
+    }
+}
+
 impl From<String> for Error {
     fn from(value: String) -> Self {
         Self::Other(value)
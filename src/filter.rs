@@ -0,0 +1,117 @@
+use macaddr::MacAddr6;
+use serde::{de, Deserialize, Deserializer};
+
+/// Devices accepted by the scanner.
+///
+/// If neither `allow` nor `names` is given, every device not in `deny` is
+/// accepted. Otherwise a device is accepted if it is in `allow` or its name
+/// matches one of the `names` patterns (where `*` matches any characters),
+/// and it is not in `deny`.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeviceFilter {
+    #[serde(deserialize_with = "de_macs")]
+    pub allow: Vec<MacAddr6>,
+    #[serde(deserialize_with = "de_macs")]
+    pub deny: Vec<MacAddr6>,
+    pub names: Vec<String>,
+}
+
+impl DeviceFilter {
+    /// Whether the filter accepts every device.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty() && self.names.is_empty()
+    }
+
+    /// Whether the filter needs the names of the devices.
+    pub fn uses_names(&self) -> bool {
+        !self.names.is_empty()
+    }
+
+    /// Whether the device with the given mac and name is accepted. Devices
+    /// without a name never match the name patterns.
+    pub fn accepts(&self, mac: MacAddr6, name: Option<&str>) -> bool {
+        if self.deny.contains(&mac) {
+            return false;
+        }
+        if self.allow.is_empty() && self.names.is_empty() {
+            return true;
+        }
+        let name_matches = |n: &str| self.names.iter().any(|p| glob_match(p, n));
+        self.allow.contains(&mac) || name.is_some_and(name_matches)
+    }
+
+    /// Whether the device without a known name is rejected only because of
+    /// the missing name, ie. it might be accepted once the name is known.
+    pub fn awaits_name(&self, mac: MacAddr6, name: Option<&str>) -> bool {
+        name.is_none() && self.uses_names() && !self.deny.contains(&mac) && !self.accepts(mac, None)
+    }
+}
+
+// mac addresses as strings (eg. "AB:CD:EF:12:34:56")
+fn de_macs<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<MacAddr6>, D::Error> {
+    let macs: Vec<String> = Vec::deserialize(d)?;
+    macs.iter()
+        .map(|s| s.parse().map_err(de::Error::custom))
+        .collect()
+}
+
+/// Match `s` against `pattern` where `*` matches any (possibly empty) sequence.
+fn glob_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always returns at least one part
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = s.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<_> = parts.collect();
+    let Some(last) = parts.pop() else {
+        // no wildcards
+        return rest.is_empty();
+    };
+    for part in parts {
+        match rest.find(part) {
+            Some(i) => rest = &rest[i + part.len()..],
+            None => return false,
+        }
+    }
+    rest.len() >= last.len() && rest.ends_with(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("Ruuvi 1234", "Ruuvi 1234"));
+        assert!(!glob_match("Ruuvi 1234", "Ruuvi 12345"));
+        assert!(glob_match("Ruuvi *", "Ruuvi 1234"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*12*4", "Ruuvi 1234"));
+        assert!(!glob_match("*12*4", "Ruuvi 1243"));
+        assert!(!glob_match("Ruuvi *", "Other 1234"));
+        assert!(!glob_match("a*a", "a"));
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let mac1 = MacAddr6::from([1; 6]);
+        let mac2 = MacAddr6::from([2; 6]);
+        let mut filter = DeviceFilter::default();
+        assert!(filter.accepts(mac1, None));
+        filter.deny.push(mac2);
+        assert!(filter.accepts(mac1, None));
+        assert!(!filter.accepts(mac2, None));
+        filter.names.push(String::from("Ruuvi *"));
+        assert!(!filter.accepts(mac1, None));
+        assert!(filter.accepts(mac1, Some("Ruuvi 0101")));
+        assert!(!filter.accepts(mac2, Some("Ruuvi 0202")));
+        assert!(filter.awaits_name(mac1, None));
+        assert!(!filter.awaits_name(mac1, Some("Other 0101")));
+        assert!(!filter.awaits_name(mac2, None));
+        filter.allow.push(mac1);
+        assert!(filter.accepts(mac1, None));
+        assert!(!filter.awaits_name(mac1, None));
+    }
+}
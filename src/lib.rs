@@ -1,9 +1,37 @@
+pub mod adapter;
 pub mod advertisements;
+pub mod aggregate;
+pub mod calibration;
+pub mod connection;
 pub mod err;
+pub mod filter;
+pub mod live;
 pub mod log;
+pub mod presence;
 pub mod ruuvi;
+pub mod stage;
+pub mod storage;
+pub mod throttle;
+pub mod timeline;
+pub mod units;
 
-pub use advertisements::print_advertisements;
+pub use adapter::{get_adapters, print_adapters, AdapterInfo};
+pub use advertisements::{
+    print_advertisements, scan, DecodeError, Presence, ScanEvent, ScanOptions,
+};
+pub use aggregate::{aggregate, print_summaries, summarize, Aggregator, Summary};
+pub use calibration::{Calibration, Calibrations, Correction};
+pub use connection::Backoff;
 pub use err::Error;
-pub use log::{get_log, print_log};
-pub use ruuvi::{Advertisement, Record};
+pub use filter::DeviceFilter;
+pub use live::{get_live_stream, print_live};
+pub use log::{
+    clear_log, get_log, get_log_stream, get_logs, get_measurement_stream, print_log, print_logs,
+    sync_log, sync_logs, LogOptions, Progress,
+};
+pub use presence::{get_devices, print_devices, DeviceStatus, PresenceTracker};
+pub use ruuvi::{Advertisement, Measurement, Observation, Record, Sensor, TaggedRecord};
+pub use storage::Storage;
+pub use throttle::{Deltas, Reduce, Throttle, Throttler};
+pub use timeline::{merge_timeline, Origin, TimelineOptions, TimelinePoint};
+pub use units::{AccelerationUnit, PressureUnit, TemperatureUnit, Units};
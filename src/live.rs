@@ -0,0 +1,44 @@
+use crate::err::{Error, Res};
+use crate::log::{connect, get_characteristic, get_service, LogOptions, UART_SVC, UART_TX};
+use crate::ruuvi::Advertisement;
+use futures::{future, pin_mut, stream, Stream, StreamExt, TryStreamExt};
+use macaddr::MacAddr6;
+
+/// Connect to the device and print the heartbeats it sends indefinitely. See
+/// [`get_live_stream`].
+#[tokio::main(flavor = "current_thread")]
+pub async fn print_live(mac: MacAddr6, opts: LogOptions) -> Res<()> {
+    let advertisements = get_live_stream(mac, &opts).await?;
+    pin_mut!(advertisements);
+    while let Some(adv) = advertisements.next().await {
+        println!("{}", opts.units.to_json(&adv?));
+    }
+    Ok(())
+}
+
+/// Connect to the device and stream the rawv5 heartbeats it sends over the
+/// UART connection, instead of listening to the advertisements.
+///
+/// The device is looked for and connected to using the adapter, discovery
+/// timeout and backoff in `opts`. The heartbeats are calibrated with
+/// `opts.calibration`. The stream ends with an error when the connection is
+/// lost.
+pub async fn get_live_stream(
+    mac: MacAddr6,
+    opts: &LogOptions,
+) -> Res<impl Stream<Item = Res<Advertisement>>> {
+    let device = connect(mac, opts).await?;
+    let calibration = opts.calibration.clone();
+    let uart_svc = get_service(&device, UART_SVC).await?;
+    let recv_char = get_characteristic(&uart_svc, UART_TX).await?;
+    let heartbeats = recv_char
+        .notify()
+        .await?
+        // other notifications (eg. log measurements) are not heartbeats
+        .filter(|v| future::ready(v.first() == Some(&0x05)))
+        .map(Advertisement::from_rawv5)
+        .map_ok(move |adv| calibration.advertisement(adv));
+    let lost = Error::Disconnected(String::from("connection lost"));
+    let lost = stream::once(future::ready(Err(lost)));
+    Ok(heartbeats.chain(lost))
+}
@@ -1,42 +1,484 @@
-use crate::err::Res;
-use crate::ruuvi::{datetime_to_bytes, Measurement, Record};
+use crate::adapter::open_adapter;
+use crate::calibration::Calibrations;
+use crate::connection::{self, Backoff};
+use crate::err::{Error, Res};
+use crate::ruuvi::{datetime_to_bytes, Measurement, Record, RecordBuilder, Sensor, TaggedRecord};
+use crate::storage::Storage;
+use crate::units::Units;
 use bluer::gatt::remote::{Characteristic, Service};
 use bluer::{Adapter, AdapterEvent, Address, Device};
 use chrono::{DateTime, Duration, Utc};
-use futures::{future, Stream, StreamExt, TryStreamExt};
+use futures::{future, pin_mut, stream, Stream, StreamExt, TryStreamExt};
 use macaddr::MacAddr6;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::io::IsTerminal;
+use std::path::Path;
+use std::sync::Arc;
+use std::time;
+use tokio::time::Instant;
 use uuid::Uuid;
 
-const UART_SVC: Uuid = Uuid::from_u128(0x6e400001b5a3f393e0a9e50e24dcca9e);
-const UART_RX: Uuid = Uuid::from_u128(0x6e400002b5a3f393e0a9e50e24dcca9e); // write
-const UART_TX: Uuid = Uuid::from_u128(0x6e400003b5a3f393e0a9e50e24dcca9e); // read
+pub(crate) const UART_SVC: Uuid = Uuid::from_u128(0x6e400001b5a3f393e0a9e50e24dcca9e);
+pub(crate) const UART_RX: Uuid = Uuid::from_u128(0x6e400002b5a3f393e0a9e50e24dcca9e); // write
+pub(crate) const UART_TX: Uuid = Uuid::from_u128(0x6e400003b5a3f393e0a9e50e24dcca9e); // read
+
+const LOG_READ: u8 = 0x11;
+const LOG_CLEAR: u8 = 0x21;
+
+/// Progress of a log download.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Progress {
+    pub mac: MacAddr6,
+    /// Number of records (or measurements) received so far.
+    pub records: usize,
+    /// Timestamp of the newest record received so far.
+    pub datetime: DateTime<Utc>,
+    /// Beginning of the requested range.
+    pub start: DateTime<Utc>,
+    /// End of the requested range.
+    pub end: DateTime<Utc>,
+}
+
+impl Progress {
+    /// Share of the requested range received, between 0 and 1.
+    pub fn fraction(&self) -> f64 {
+        let total = (self.end - self.start).num_seconds();
+        let done = (self.datetime - self.start).num_seconds();
+        if total <= 0 {
+            return 1.0;
+        }
+        (done as f64 / total as f64).clamp(0.0, 1.0)
+    }
+}
+
+impl std::fmt::Display for Progress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} records, {} ({:.0} %)",
+            self.mac,
+            self.records,
+            self.datetime.to_rfc3339(),
+            100.0 * self.fraction()
+        )
+    }
+}
+
+pub type ProgressFn = Arc<dyn Fn(&Progress)>;
+
+/// Options for log downloads.
+#[derive(Clone)]
+pub struct LogOptions {
+    /// Name of the bluetooth adapter, the default adapter if `None`.
+    pub adapter: Option<String>,
+    /// Maximum duration of the whole download.
+    pub timeout: Option<time::Duration>,
+    /// Maximum time to wait for the next notification from the device.
+    pub idle_timeout: Option<time::Duration>,
+    /// Called after each received record.
+    pub progress: Option<ProgressFn>,
+    /// Maximum time to look for the devices.
+    pub discovery_timeout: Option<time::Duration>,
+    /// Number of logs downloaded at the same time.
+    pub concurrency: usize,
+    /// Number of times a download that failed due to a lost connection or a
+    /// timeout is resumed after reconnecting, when downloading multiple logs or
+    /// syncing.
+    pub retries: u8,
+    /// Backoff between the attempts to connect to a device.
+    pub backoff: Backoff,
+    /// Sensors whose logs are downloaded (one after another) when downloading
+    /// multiple logs, all of them at once if empty.
+    pub sensors: Vec<Sensor>,
+    /// Measurements after this are dropped. The device always sends the log
+    /// until the current time, so this does not make the download faster.
+    pub until: Option<DateTime<Utc>>,
+    /// Corrections applied to the returned measurements. The records stored by
+    /// [`sync_log`] are always raw.
+    pub calibration: Calibrations,
+    /// Units of the printed records.
+    pub units: Units,
+}
+
+impl Default for LogOptions {
+    fn default() -> Self {
+        Self {
+            adapter: None,
+            timeout: None,
+            idle_timeout: Some(time::Duration::from_secs(30)),
+            progress: None,
+            discovery_timeout: Some(time::Duration::from_secs(60)),
+            concurrency: 1,
+            retries: 2,
+            backoff: Backoff::default(),
+            sensors: vec![],
+            until: None,
+            calibration: Calibrations::default(),
+            units: Units::default(),
+        }
+    }
+}
+
+impl std::fmt::Debug for LogOptions {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogOptions")
+            .field("adapter", &self.adapter)
+            .field("timeout", &self.timeout)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("progress", &self.progress.is_some())
+            .field("discovery_timeout", &self.discovery_timeout)
+            .field("concurrency", &self.concurrency)
+            .field("retries", &self.retries)
+            .field("backoff", &self.backoff)
+            .field("sensors", &self.sensors)
+            .field("until", &self.until)
+            .field("calibration", &self.calibration)
+            .field("units", &self.units)
+            .finish()
+    }
+}
+
+impl LogOptions {
+    /// Options with progress printed on stderr, if it is a terminal (eg. not
+    /// when running from cron).
+    fn print_progress(self) -> Self {
+        if !std::io::stderr().is_terminal() {
+            return self;
+        }
+        Self {
+            progress: Some(Arc::new(|p| eprint!("\r{}\x1b[K", p))),
+            ..self
+        }
+    }
+
+    /// End the progress line, if it is printed.
+    fn end_progress(&self) {
+        if self.progress.is_some() {
+            eprintln!();
+        }
+    }
+
+    fn sensors(&self) -> Vec<Sensor> {
+        match self.sensors.as_slice() {
+            [] => vec![Sensor::All],
+            sensors => sensors.to_vec(),
+        }
+    }
+}
 
 /// Print log for the last `n_hours`. See [`get_log`].
 #[tokio::main(flavor = "current_thread")]
 pub async fn print_log(mac: MacAddr6, n_hours: u8) -> Res<()> {
     let begin_ts = Utc::now() - Duration::hours(n_hours.into());
-    for r in get_log(mac, begin_ts).await? {
+    let opts = LogOptions::default().print_progress();
+    let records = get_log_stream(mac, begin_ts, &opts).await?;
+    pin_mut!(records);
+    let mut missing = 0;
+    while let Some(r) = records.next().await {
+        let r = r?;
+        missing += r.missing();
         println!("{}", r);
     }
+    opts.end_progress();
+    if missing > 0 {
+        eprintln!("{} measurements missing from the log", missing);
+    }
     Ok(())
 }
 
-/// Get log starting from `log_start`.
+/// Print logs of all the devices in `macs` starting from `log_start`, followed
+/// by a summary on stderr. See [`get_logs`].
+#[tokio::main(flavor = "current_thread")]
+pub async fn print_logs(
+    macs: Vec<MacAddr6>,
+    log_start: DateTime<Utc>,
+    opts: LogOptions,
+) -> Res<()> {
+    let opts = opts.print_progress();
+    // logs of single sensors consist of partial records
+    let count_missing = opts.sensors() == [Sensor::All];
+    let missing = RefCell::new(HashMap::new());
+    let print = |r: TaggedRecord| {
+        if count_missing {
+            *missing.borrow_mut().entry(r.mac).or_insert(0) += r.record.missing();
+        }
+        println!("{}", opts.units.to_json(&r))
+    };
+    let results = get_logs(&macs, log_start, &opts, print).await?;
+    opts.end_progress();
+    let missing = missing.into_inner();
+    let mut failed = vec![];
+    for (mac, res) in results {
+        match res {
+            Ok(n) => {
+                eprintln!("{}: {} records", mac, n);
+                match missing.get(&mac) {
+                    Some(&m) if m > 0 => {
+                        eprintln!("{}: {} measurements missing from the log", mac, m)
+                    }
+                    _ => {}
+                }
+            }
+            Err(e) => {
+                eprintln!("{}: {}", mac, e);
+                failed.push((mac, e));
+            }
+        }
+    }
+    failure("unable to get log from", failed)
+}
+
+/// Get the logs of all the devices in `macs` starting from `log_start`.
 ///
-/// If `log_start` is newer than current timestamp - 2 minutes or older than
-/// current timestamp - 240 hours, it is set to those limits.
-pub async fn get_log(mac: MacAddr6, log_start: DateTime<Utc>) -> Res<Vec<Record>> {
+/// The devices are looked for in a single discovery, after which at most
+/// `opts.concurrency` logs are downloaded at a time. `on_record` is called
+/// for each record as it is received. A failed download is retried
+/// `opts.retries` times after a lost connection or a timeout, continuing from
+/// the last received record and reporting the failed attempts on stderr. With
+/// `opts.sensors`, each measurement is returned as a partial record. The
+/// records are calibrated with `opts.calibration`.
+///
+/// Returns the number of records received or the error from the last attempt
+/// for each device.
+pub async fn get_logs(
+    macs: &[MacAddr6],
+    log_start: DateTime<Utc>,
+    opts: &LogOptions,
+    on_record: impl Fn(TaggedRecord),
+) -> Res<Vec<(MacAddr6, Res<usize>)>> {
     let session = bluer::Session::new().await?;
-    let adapter = session.default_adapter().await?;
-    adapter.set_powered(true).await?;
+    let adapter = open_adapter(&session, opts.adapter.as_deref()).await?;
+    let mut devices = find_devices(&adapter, macs, opts.discovery_timeout).await?;
+    let on_record = &|r: TaggedRecord| {
+        let record = opts.calibration.record(r.mac, r.record);
+        on_record(TaggedRecord { mac: r.mac, record });
+        Ok(())
+    };
+    let results = stream::iter(macs)
+        .map(|&mac| {
+            let device = devices.remove(&mac);
+            async move {
+                let res = match device {
+                    Some(dev) => get_log_with_retries(&dev, mac, log_start, opts, on_record).await,
+                    None => Err(not_found(mac, opts.discovery_timeout)),
+                };
+                (mac, res)
+            }
+        })
+        .buffer_unordered(opts.concurrency.max(1))
+        .collect()
+        .await;
+    Ok(results)
+}
+
+async fn get_log_with_retries(
+    device: &Device,
+    mac: MacAddr6,
+    log_start: DateTime<Utc>,
+    opts: &LogOptions,
+    on_record: impl Fn(TaggedRecord) -> Res<()>,
+) -> Res<usize> {
+    let mut n = 0;
+    for sensor in opts.sensors() {
+        n += get_sensor_log_with_retries(device, mac, sensor, log_start, opts, &on_record).await?;
+    }
+    Ok(n)
+}
+
+async fn get_sensor_log_with_retries(
+    device: &Device,
+    mac: MacAddr6,
+    sensor: Sensor,
+    mut log_start: DateTime<Utc>,
+    opts: &LogOptions,
+    on_record: impl Fn(TaggedRecord) -> Res<()>,
+) -> Res<usize> {
+    let mut n = 0;
+    let mut attempt = 0;
+    let mut last = None;
+    loop {
+        let res = async {
+            connection::connect(device, &opts.backoff).await?;
+            let records = get_records(device, mac, sensor, log_start, opts).await?;
+            pin_mut!(records);
+            while let Some(record) = records.next().await {
+                let record = record?;
+                // the last record is sent again after resuming
+                if last.as_ref() == Some(&record) {
+                    continue;
+                }
+                // resuming from the same timestamp gets the rest of a partial record
+                log_start = log_start.max(record.datetime);
+                last = Some(record.clone());
+                n += 1;
+                on_record(TaggedRecord { mac, record })?;
+            }
+            Ok::<_, Error>(())
+        }
+        .await;
+        match res {
+            Ok(()) => return Ok(n),
+            Err(e) if attempt >= opts.retries || !is_retryable(&e) => return Err(e),
+            Err(e) => {
+                attempt += 1;
+                eprintln!("{}: attempt {} failed: {}", mac, attempt, e);
+            }
+        }
+    }
+}
+
+// only a lost connection or a stalled download can succeed on another attempt
+fn is_retryable(e: &Error) -> bool {
+    matches!(e, Error::Disconnected(_) | Error::Timeout(_))
+}
+
+/// Store the new log records of each device in `macs` into the database at
+/// `db`, clearing the logs from the devices afterwards if `clear_log` is set.
+/// See [`sync_log`].
+#[tokio::main(flavor = "current_thread")]
+pub async fn sync_logs(
+    db: impl AsRef<Path>,
+    macs: Vec<MacAddr6>,
+    clear_log: bool,
+    opts: LogOptions,
+) -> Res<()> {
+    let mut storage = Storage::open(db)?;
+    let opts = opts.print_progress();
+    let mut failed = vec![];
+    for mac in macs {
+        let res = sync_log(&mut storage, mac, &opts, clear_log).await;
+        opts.end_progress();
+        match res {
+            Ok(n) => eprintln!("{}: {} new records", mac, n),
+            Err(e) => {
+                eprintln!("{}: {}", mac, e);
+                failed.push((mac, e));
+            }
+        }
+    }
+    failure("unable to sync", failed)
+}
+
+/// Error listing the devices that failed, [`Error::NotFound`] if none of them
+/// were found.
+fn failure(msg: &str, failed: Vec<(MacAddr6, Error)>) -> Res<()> {
+    if failed.is_empty() {
+        return Ok(());
+    }
+    let macs: Vec<_> = failed.iter().map(|(mac, _)| mac.to_string()).collect();
+    let msg = format!("{} {}", msg, macs.join(", "));
+    match failed.iter().all(|(_, e)| matches!(e, Error::NotFound(_))) {
+        true => Err(Error::NotFound(msg)),
+        false => Err(Error::Other(msg)),
+    }
+}
+
+/// Get the log records that are newer than the latest record stored for `mac`
+/// and append them to `storage` as is, without `opts.calibration`. Returns the
+/// number of new records.
+///
+/// The download starts from the latest stored record, so the measurements
+/// missing from it (eg. after an interrupted sync) are filled in. If nothing
+/// has been stored yet, the whole log is downloaded. If the device
+/// disconnects or the download times out, it is resumed after reconnecting
+/// `opts.retries` times. Each record is stored as soon as it is received, so
+/// the records received before a failure are kept.
+///
+/// If `clear_log` is set, the log is cleared from the device after the whole
+/// download has been stored. Measurements logged by the device after the
+/// download has finished are lost.
+pub async fn sync_log(
+    storage: &mut Storage,
+    mac: MacAddr6,
+    opts: &LogOptions,
+    clear_log: bool,
+) -> Res<usize> {
+    // the latest record is requested again in case it is partial
+    let latest = storage.latest_record(mac)?;
+    let log_start = latest.unwrap_or_else(|| Utc::now() - Duration::hours(240));
+    let device = connect(mac, opts).await?;
+    let storage = RefCell::new(storage);
+    let (newest, n) = (Cell::new(latest), Cell::new(0));
+    // stored as they are received to keep them even if the process is killed
+    let insert = |r: TaggedRecord| {
+        storage.borrow_mut().insert_records(mac, [&r.record])?;
+        if Some(r.record.datetime) > newest.get() {
+            newest.set(Some(r.record.datetime));
+            n.set(n.get() + 1);
+        }
+        Ok(())
+    };
+    get_sensor_log_with_retries(&device, mac, Sensor::All, log_start, opts, insert).await?;
+    if clear_log {
+        clear_device_log(&device).await?;
+    }
+    Ok(n.get())
+}
+
+/// Clear the history log from the device.
+pub async fn clear_log(mac: MacAddr6, opts: &LogOptions) -> Res<()> {
+    let device = connect(mac, opts).await?;
+    clear_device_log(&device).await
+}
+
+/// Get log starting from `log_start`. See [`get_log_stream`].
+pub async fn get_log(
+    mac: MacAddr6,
+    log_start: DateTime<Utc>,
+    opts: &LogOptions,
+) -> Res<Vec<Record>> {
+    get_log_stream(mac, log_start, opts)
+        .await?
+        .try_collect()
+        .await
+}
+
+/// Stream log records starting from `log_start` as they are received.
+///
+/// If `log_start` is newer than current timestamp - 2 minutes or older than
+/// current timestamp - 240 hours, it is set to those limits. Records with
+/// measurements missing from the log are returned as partial records. The
+/// stream ends after the first error, including a connection lost before the
+/// whole log has been received and the timeouts in `opts`. The records are
+/// calibrated with `opts.calibration`.
+pub async fn get_log_stream(
+    mac: MacAddr6,
+    log_start: DateTime<Utc>,
+    opts: &LogOptions,
+) -> Res<impl Stream<Item = Res<Record>>> {
+    let device = connect(mac, opts).await?;
+    let calibration = opts.calibration.clone();
+    let records = get_records(&device, mac, Sensor::All, log_start, opts).await?;
+    Ok(records.map_ok(move |r| calibration.record(mac, r)))
+}
+
+/// Stream the log measurements of `sensor` starting from `log_start` as they are
+/// received. See [`get_log_stream`].
+pub async fn get_measurement_stream(
+    mac: MacAddr6,
+    sensor: Sensor,
+    log_start: DateTime<Utc>,
+    opts: &LogOptions,
+) -> Res<impl Stream<Item = Res<Measurement>>> {
+    let device = connect(mac, opts).await?;
+    let calibration = opts.calibration.clone();
+    let measurements = get_measurements(&device, mac, sensor, log_start, opts).await?;
+    Ok(measurements.map_ok(move |m| calibration.measurement(mac, m)))
+}
 
-    let device = find_device(&adapter, mac).await?;
-    try_to_connect(&device, 3).await?;
+/// Find and connect to the device using the adapter, discovery timeout and
+/// backoff in `opts`.
+pub(crate) async fn connect(mac: MacAddr6, opts: &LogOptions) -> Res<Device> {
+    let session = bluer::Session::new().await?;
+    let adapter = open_adapter(&session, opts.adapter.as_deref()).await?;
 
-    get_records(&device, log_start).await
+    let device = find_device(&adapter, mac, opts.discovery_timeout).await?;
+    connection::connect(&device, &opts.backoff).await?;
+    Ok(device)
 }
 
-async fn get_service(device: &Device, uuid: Uuid) -> Res<Service> {
+pub(crate) async fn get_service(device: &Device, uuid: Uuid) -> Res<Service> {
     for svc in device.services().await? {
         if svc.uuid().await? == uuid {
             return Ok(svc);
@@ -45,7 +487,7 @@ async fn get_service(device: &Device, uuid: Uuid) -> Res<Service> {
     Err(format!("unable to find service with uuid {}", uuid))?
 }
 
-async fn get_characteristic(svc: &Service, uuid: Uuid) -> Res<Characteristic> {
+pub(crate) async fn get_characteristic(svc: &Service, uuid: Uuid) -> Res<Characteristic> {
     for char in svc.characteristics().await? {
         if char.uuid().await? == uuid {
             return Ok(char);
@@ -56,19 +498,17 @@ async fn get_characteristic(svc: &Service, uuid: Uuid) -> Res<Characteristic> {
 
 async fn get_event_stream(
     device: &Device,
-    log_start: DateTime<Utc>,
+    sensor: Sensor,
+    start_ts: DateTime<Utc>,
+    current_ts: DateTime<Utc>,
 ) -> Res<impl Stream<Item = Vec<u8>>> {
     let uart_svc = get_service(device, UART_SVC).await?;
     let recv_char = get_characteristic(&uart_svc, UART_TX).await?;
     let send_char = get_characteristic(&uart_svc, UART_RX).await?;
     let stream = recv_char.notify().await?;
 
-    let current_ts = Utc::now();
-    let start_ts = log_start
-        .min(current_ts - Duration::minutes(1))
-        .max(current_ts - Duration::hours(240));
     let data = [
-        &[0x3A, 0x3A, 0x11],
+        &[sensor.endpoint(), sensor.endpoint(), LOG_READ],
         datetime_to_bytes(current_ts)?.as_slice(),
         datetime_to_bytes(start_ts)?.as_slice(),
     ]
@@ -78,37 +518,393 @@ async fn get_event_stream(
     Ok(stream)
 }
 
-async fn get_records(device: &Device, log_start: DateTime<Utc>) -> Res<Vec<Record>> {
-    let stream = get_event_stream(device, log_start).await?;
-    let measurements: Vec<_> = stream
-        .filter_map(Measurement::from_11_bytes)
-        .try_take_while(|x| future::ready(Ok(x != &Measurement::EndOfMeasurements)))
-        .try_collect()
-        .await?;
-    measurements.chunks(3).map(Record::from_chunk).collect()
+async fn clear_device_log(device: &Device) -> Res<()> {
+    let uart_svc = get_service(device, UART_SVC).await?;
+    let send_char = get_characteristic(&uart_svc, UART_RX).await?;
+    let all = Sensor::All.endpoint();
+    let data = [[all, all, LOG_CLEAR].as_slice(), &[0x00; 8]].concat();
+    send_char.write(&data).await?;
+    Ok(())
+}
+
+async fn start_log(
+    device: &Device,
+    mac: MacAddr6,
+    sensor: Sensor,
+    log_start: DateTime<Utc>,
+    opts: &LogOptions,
+) -> Res<LogState<impl Stream<Item = Option<Vec<u8>>> + Unpin>> {
+    let current_ts = Utc::now();
+    let start_ts = log_start
+        .min(current_ts - Duration::minutes(1))
+        .max(current_ts - Duration::hours(240));
+    let disconnections = connection::disconnections(device).await?.map(|_| None);
+    let events = get_event_stream(device, sensor, start_ts, current_ts)
+        .await?
+        // other notifications (eg. heartbeats) are not log measurements
+        .filter(|v| future::ready(v.len() == 11))
+        .map(Some);
+    // None signals a disconnection
+    let events = Box::pin(stream::select(events, disconnections));
+    let progress = Progress {
+        mac,
+        records: 0,
+        datetime: start_ts,
+        start: start_ts,
+        end: current_ts,
+    };
+    Ok(LogState::new(events, sensor, progress, opts))
+}
+
+async fn get_records(
+    device: &Device,
+    mac: MacAddr6,
+    sensor: Sensor,
+    log_start: DateTime<Utc>,
+    opts: &LogOptions,
+) -> Res<impl Stream<Item = Res<Record>>> {
+    let state = start_log(device, mac, sensor, log_start, opts).await?;
+    Ok(stream::unfold(state, |mut state| async move {
+        let res = state.next_record().await?;
+        if let Ok(r) = &res {
+            state.report(r.datetime);
+        }
+        Some((res, state))
+    }))
+}
+
+async fn get_measurements(
+    device: &Device,
+    mac: MacAddr6,
+    sensor: Sensor,
+    log_start: DateTime<Utc>,
+    opts: &LogOptions,
+) -> Res<impl Stream<Item = Res<Measurement>>> {
+    let state = start_log(device, mac, sensor, log_start, opts).await?;
+    Ok(stream::unfold(state, |mut state| async move {
+        let res = state.next_measurement().await?;
+        if let Some(ts) = res.as_ref().ok().and_then(Measurement::datetime) {
+            state.report(ts);
+        }
+        Some((res, state))
+    }))
+}
+
+struct LogState<S> {
+    events: S,
+    sensor: Sensor,
+    builder: RecordBuilder,
+    ended: bool,
+    error: Option<Error>,
+    deadline: Option<Instant>,
+    opts: LogOptions,
+    progress: Progress,
+}
+
+impl<S: Stream<Item = Option<Vec<u8>>> + Unpin> LogState<S> {
+    /// State of a log download from `events`, where `None` signals a
+    /// disconnection. The overall timeout starts now.
+    fn new(events: S, sensor: Sensor, progress: Progress, opts: &LogOptions) -> Self {
+        Self {
+            events,
+            sensor,
+            builder: RecordBuilder::default(),
+            ended: false,
+            error: None,
+            deadline: opts.timeout.map(|t| Instant::now() + t),
+            opts: opts.clone(),
+            progress,
+        }
+    }
+
+    /// Next measurement or `None` after the end of the log. The log ends after
+    /// an error. Measurements after `opts.until` are skipped.
+    async fn next_measurement(&mut self) -> Option<Res<Measurement>> {
+        if self.ended {
+            return None;
+        }
+        let res = loop {
+            match self.next_event().await {
+                Ok(Some(Measurement::EndOfMeasurements)) => break None,
+                Ok(Some(m)) if self.is_after_until(&m) => continue,
+                Ok(Some(m)) => return Some(Ok(m)),
+                Ok(None) => break Some(Err(disconnected())),
+                Err(e) => break Some(Err(e)),
+            }
+        };
+        self.ended = true;
+        res
+    }
+
+    /// Next record or `None` after the end of the log. Records still pending
+    /// in the builder are returned before a possible error. When logging a
+    /// single sensor, each measurement is returned as a partial record.
+    async fn next_record(&mut self) -> Option<Res<Record>> {
+        while let Some(res) = self.next_measurement().await {
+            match res {
+                Ok(m) if self.sensor != Sensor::All => return Some(Record::try_from(m)),
+                Ok(m) => {
+                    if let Some(r) = self.builder.push(m) {
+                        return Some(Ok(r));
+                    }
+                }
+                Err(e) => self.error = Some(e),
+            }
+        }
+        if let Some(r) = self.builder.pop() {
+            return Some(Ok(r));
+        }
+        self.error.take().map(Err)
+    }
+
+    async fn next_event(&mut self) -> Res<Option<Measurement>> {
+        let idle_deadline = self.opts.idle_timeout.map(|t| Instant::now() + t);
+        let deadline = [self.deadline, idle_deadline].into_iter().flatten().min();
+        let next = self.events.next();
+        let v = match deadline {
+            Some(d) => tokio::time::timeout_at(d, next)
+                .await
+                .map_err(|_| self.timeout_error(d))?,
+            None => next.await,
+        };
+        match v {
+            Some(Some(v)) => Measurement::from_bytes(v).map(Some),
+            Some(None) => Err(disconnected()),
+            None => Ok(None),
+        }
+    }
+
+    fn is_after_until(&self, measurement: &Measurement) -> bool {
+        match (self.opts.until, measurement.datetime()) {
+            (Some(until), Some(ts)) => ts > until,
+            _ => false,
+        }
+    }
+
+    fn timeout_error(&self, deadline: Instant) -> Error {
+        let msg = match (self.deadline, self.opts.timeout, self.opts.idle_timeout) {
+            (Some(d), Some(t), _) if d == deadline => {
+                format!("log download did not finish in {} s", t.as_secs())
+            }
+            (_, _, t) => format!(
+                "no log data received in {} s",
+                t.unwrap_or_default().as_secs()
+            ),
+        };
+        Error::Timeout(msg)
+    }
+
+    fn report(&mut self, datetime: DateTime<Utc>) {
+        self.progress.records += 1;
+        self.progress.datetime = self.progress.datetime.max(datetime);
+        if let Some(f) = &self.opts.progress {
+            f(&self.progress);
+        }
+    }
 }
 
-async fn find_device(adapter: &Adapter, mac: MacAddr6) -> Res<Device> {
-    let mac = Address::from(mac);
+fn disconnected() -> Error {
+    Error::Disconnected(String::from("connection lost before the end of the log"))
+}
+
+/// Look for all the devices in `macs`, until all of them have been found or
+/// `timeout` has passed. Devices already known to BlueZ are used without
+/// discovery.
+async fn find_devices(
+    adapter: &Adapter,
+    macs: &[MacAddr6],
+    timeout: Option<time::Duration>,
+) -> Res<HashMap<MacAddr6, Device>> {
+    let mut missing: HashSet<_> = macs.iter().map(|&mac| Address::from(mac)).collect();
+    let mut devices = HashMap::new();
+    for addr in adapter.device_addresses().await? {
+        if missing.remove(&addr) {
+            devices.insert(MacAddr6::from(addr), adapter.device(addr)?);
+        }
+    }
+    if missing.is_empty() {
+        return Ok(devices);
+    }
     let mut discover = adapter.discover_devices().await?;
-    while let Some(evt) = discover.next().await {
-        if let AdapterEvent::DeviceAdded(addr) = evt {
-            if addr == mac {
-                return Ok(adapter.device(addr)?);
+    let deadline = timeout.map(|t| Instant::now() + t);
+    while !missing.is_empty() {
+        let evt = match deadline {
+            Some(d) => match tokio::time::timeout_at(d, discover.next()).await {
+                Ok(evt) => evt,
+                Err(_) => break,
+            },
+            None => discover.next().await,
+        };
+        match evt {
+            Some(AdapterEvent::DeviceAdded(addr)) if missing.remove(&addr) => {
+                devices.insert(MacAddr6::from(addr), adapter.device(addr)?);
             }
+            Some(_) => {}
+            None => break,
         }
     }
-    Err("unable to find device")?
+    Ok(devices)
 }
 
-async fn try_to_connect(device: &Device, max_tries: u8) -> Res<()> {
-    if device.is_connected().await? {
-        return Ok(());
+/// Look for the device until it has been found or `timeout` has passed.
+async fn find_device(
+    adapter: &Adapter,
+    mac: MacAddr6,
+    timeout: Option<time::Duration>,
+) -> Res<Device> {
+    let mut devices = find_devices(adapter, &[mac], timeout).await?;
+    devices.remove(&mac).ok_or_else(|| not_found(mac, timeout))
+}
+
+fn not_found(mac: MacAddr6, timeout: Option<time::Duration>) -> Error {
+    match timeout {
+        Some(t) => Error::NotFound(format!("{} not found within {} s", mac, t.as_secs())),
+        None => Error::NotFound(format!("{} not found", mac)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_fraction() {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut progress = Progress {
+            mac: MacAddr6::nil(),
+            records: 0,
+            datetime: start,
+            start,
+            end: start + Duration::hours(10),
+        };
+        assert_eq!(progress.fraction(), 0.0);
+        progress.datetime = start + Duration::hours(4);
+        assert_eq!(progress.fraction(), 0.4);
+        progress.datetime = start + Duration::hours(11);
+        assert_eq!(progress.fraction(), 1.0);
+    }
+
+    #[test]
+    fn failure_kind() {
+        let mac = MacAddr6::nil();
+        let not_found = || Error::NotFound(String::from("not found"));
+        assert!(failure("unable to sync", vec![]).is_ok());
+        let res = failure(
+            "unable to sync",
+            vec![(mac, not_found()), (mac, not_found())],
+        );
+        assert!(matches!(res, Err(Error::NotFound(_))));
+        let res = failure(
+            "unable to sync",
+            vec![(mac, not_found()), (mac, "lost".into())],
+        );
+        assert!(matches!(res, Err(Error::Other(_))));
+    }
+
+    fn measurement(sensor: u8, secs: i64, value: u32) -> Vec<u8> {
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000 + secs, 0).unwrap();
+        let ts = datetime_to_bytes(ts).unwrap();
+        [[0x3A, sensor, 0x10].as_slice(), &ts, &value.to_be_bytes()].concat()
+    }
+
+    fn log_state<S: Stream<Item = Option<Vec<u8>>> + Unpin>(
+        events: S,
+        opts: &LogOptions,
+    ) -> LogState<S> {
+        let start = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let progress = Progress {
+            mac: MacAddr6::nil(),
+            records: 0,
+            datetime: start,
+            start,
+            end: start + Duration::hours(1),
+        };
+        LogState::new(events, Sensor::All, progress, opts)
+    }
+
+    #[tokio::test]
+    async fn disconnection_is_retryable() {
+        let events = stream::iter([Some(measurement(0x30, 0, 2050)), None]);
+        let mut state = log_state(events, &LogOptions::default());
+        let record = state.next_record().await.unwrap().unwrap();
+        assert_eq!(record.temperature, Some(20.5));
+        assert_eq!(record.humidity, None);
+        let err = state.next_record().await.unwrap().unwrap_err();
+        assert!(matches!(err, Error::Disconnected(_)));
+        assert!(is_retryable(&err));
+        assert!(state.next_record().await.is_none());
     }
-    for _ in 0..max_tries {
-        if device.connect().await.is_ok() {
-            return Ok(());
+
+    #[tokio::test]
+    async fn missing_measurement() {
+        // humidity of the second record is missing and the order is mixed
+        let events = stream::iter([
+            Some(measurement(0x30, 0, 2050)),
+            Some(measurement(0x31, 0, 4000)),
+            Some(measurement(0x30, 300, 2100)),
+            Some(measurement(0x32, 0, 100000)),
+            Some(measurement(0x32, 300, 100100)),
+            None,
+        ]);
+        let mut state = log_state(events, &LogOptions::default());
+        let record = state.next_record().await.unwrap().unwrap();
+        assert_eq!(record.missing(), 0);
+        assert_eq!(record.humidity, Some(40.0));
+        let record = state.next_record().await.unwrap().unwrap();
+        assert_eq!(record.datetime.timestamp(), 1_700_000_300);
+        assert_eq!(record.air_pressure, Some(100100));
+        assert_eq!(record.humidity, None);
+        assert!(state.next_record().await.unwrap().is_err());
+        assert!(state.next_record().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeouts() {
+        let events = || stream::iter([Some(measurement(0x30, 0, 2050))]).chain(stream::pending());
+        let mut state = log_state(events(), &LogOptions::default());
+        let start = Instant::now();
+        assert!(state.next_record().await.unwrap().is_ok());
+        let err = state.next_record().await.unwrap().unwrap_err();
+        assert!(matches!(err, Error::Timeout(_)));
+        assert_eq!(err.to_string(), "no log data received in 30 s");
+        assert_eq!(start.elapsed(), time::Duration::from_secs(30));
+
+        let opts = LogOptions {
+            timeout: Some(time::Duration::from_secs(10)),
+            ..Default::default()
+        };
+        let mut state = log_state(events(), &opts);
+        assert!(state.next_record().await.unwrap().is_ok());
+        let err = state.next_record().await.unwrap().unwrap_err();
+        assert_eq!(err.to_string(), "log download did not finish in 10 s");
+    }
+
+    #[tokio::test]
+    async fn until() {
+        let end = [[0x3A, 0x3A, 0x10].as_slice(), &[0xFF; 8]].concat();
+        let events = stream::iter([
+            Some(measurement(0x30, 0, 2050)),
+            Some(measurement(0x30, 300, 2100)),
+            Some(measurement(0x30, 600, 2150)),
+            Some(end),
+        ]);
+        let opts = LogOptions {
+            until: DateTime::<Utc>::from_timestamp(1_700_000_300, 0),
+            ..Default::default()
+        };
+        let mut state = log_state(events, &opts);
+        let mut datetimes = vec![];
+        while let Some(m) = state.next_measurement().await {
+            datetimes.push(m.unwrap().datetime().unwrap().timestamp());
         }
+        assert_eq!(datetimes, vec![1_700_000_000, 1_700_000_300]);
+    }
+
+    #[test]
+    fn retryable_errors() {
+        assert!(is_retryable(&Error::Disconnected(String::from("lost"))));
+        assert!(is_retryable(&Error::Timeout(String::from("idle"))));
+        assert!(!is_retryable(&Error::Parse(String::from("invalid"))));
+        assert!(!is_retryable(&"service not found".into()));
     }
-    Err(format!("unable to connect device after {}", max_tries))?
 }
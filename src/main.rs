@@ -1,5 +1,5 @@
 use config::Config;
-use ruuvi::advertisements;
+use ruuvi::{advertisements, Error};
 use std::{env, process};
 
 mod config;
@@ -11,12 +11,26 @@ fn main() {
     });
 
     match config {
-        Config::Latest(v) => ruuvi::advertisements::print_advertisements(Some(v)),
-        Config::Log(mac, n) => ruuvi::log::print_log(mac, n),
-        Config::Scan => advertisements::print_advertisements(None),
+        Config::Adapters => ruuvi::adapter::print_adapters(),
+        Config::Aggregate(interval, db, opts) => {
+            ruuvi::aggregate::print_summaries(interval, db, &opts)
+        }
+        Config::Devices(opts) => ruuvi::presence::print_devices(&opts),
+        Config::Latest(v, opts) => advertisements::print_advertisements(Some(v), &opts),
+        Config::Live(mac, opts) => ruuvi::live::print_live(mac, opts),
+        Config::Log(macs, since, opts) => ruuvi::log::print_logs(macs, since, opts),
+        Config::Scan(opts) => advertisements::print_advertisements(None, &opts),
+        Config::Sync(db, macs, clear_log, opts) => ruuvi::log::sync_logs(db, macs, clear_log, opts),
     }
     .unwrap_or_else(|e| {
         eprintln!("{}", e);
-        process::exit(1);
+        process::exit(exit_code(&e));
     });
 }
+
+fn exit_code(e: &Error) -> i32 {
+    match e {
+        Error::NotFound(_) => 2,
+        _ => 1,
+    }
+}
@@ -0,0 +1,242 @@
+use crate::advertisements::{scan, Presence, ScanEvent, ScanOptions};
+use crate::err::Res;
+use crate::ruuvi::{ser_dt, ser_mac, Observation};
+use crate::stage::Stage;
+use chrono::{DateTime, Utc};
+use futures::{pin_mut, StreamExt};
+use macaddr::MacAddr6;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashSet};
+use tokio::time::Instant;
+
+// weight of the latest interval in the average
+const INTERVAL_WEIGHT: f64 = 0.125;
+
+/// Presence of a device based on its advertisements.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DeviceStatus {
+    #[serde(serialize_with = "ser_mac")]
+    pub mac: MacAddr6,
+    pub online: bool,
+    #[serde(serialize_with = "ser_dt")]
+    pub last_seen: DateTime<Utc>,
+    /// Adapter that received the latest advertisement.
+    pub adapter: String,
+    pub rssi: Option<i16>,
+    /// Average interval between the advertisements in seconds.
+    pub interval: Option<f64>,
+    pub advertisements: usize,
+}
+
+impl std::fmt::Display for DeviceStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // to_string really should not fail...
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+impl DeviceStatus {
+    fn new(presence: &Presence) -> Self {
+        Self {
+            mac: presence.mac,
+            online: false,
+            last_seen: presence.datetime,
+            adapter: presence.adapter.clone(),
+            rssi: None,
+            interval: None,
+            advertisements: 0,
+        }
+    }
+
+    /// Time after which the device is considered offline.
+    fn deadline(&self, offline_factor: f64) -> Option<DateTime<Utc>> {
+        let millis = self.interval? * offline_factor * 1000.0;
+        Some(self.last_seen + chrono::Duration::milliseconds(millis as i64))
+    }
+}
+
+/// Keeps track of the devices seen in [`ScanEvent`]s, see [`Stage`].
+///
+/// A device is reported offline if it has been silent for `offline_factor`
+/// times its average advertisement interval, and online again when the next
+/// advertisement is received.
+#[derive(Debug)]
+pub struct PresenceTracker {
+    offline_factor: f64,
+    devices: BTreeMap<MacAddr6, DeviceStatus>,
+    // devices whose next advertisement does not follow the previous one
+    resumed: HashSet<MacAddr6>,
+}
+
+impl PresenceTracker {
+    pub fn new(offline_factor: f64) -> Self {
+        Self {
+            offline_factor,
+            devices: BTreeMap::new(),
+            resumed: HashSet::new(),
+        }
+    }
+
+    /// Every device seen so far, ordered by mac address.
+    pub fn devices(&self) -> impl Iterator<Item = &DeviceStatus> {
+        self.devices.values()
+    }
+
+    fn online(&mut self, presence: Presence) -> Option<ScanEvent> {
+        let status = self
+            .devices
+            .entry(presence.mac)
+            .or_insert_with(|| DeviceStatus::new(&presence));
+        if status.online {
+            return None;
+        }
+        status.online = true;
+        self.resumed.insert(presence.mac);
+        Some(ScanEvent::Online(presence))
+    }
+
+    fn observe(&mut self, obs: &Observation) {
+        let mac = obs.advertisement.mac();
+        let Some(status) = self.devices.get_mut(&mac) else {
+            return;
+        };
+        if !self.resumed.remove(&mac) {
+            let millis = (obs.datetime - status.last_seen).num_milliseconds();
+            let interval = millis as f64 / 1000.0;
+            status.interval = Some(match status.interval {
+                Some(avg) => avg + INTERVAL_WEIGHT * (interval - avg),
+                None => interval,
+            });
+        }
+        status.last_seen = obs.datetime;
+        status.adapter = obs.adapter.clone();
+        status.rssi = obs.rssi;
+        status.advertisements += 1;
+    }
+}
+
+impl Stage for PresenceTracker {
+    type Output = ScanEvent;
+
+    /// Updates the device statuses with the event. Passes on the event unless
+    /// it is a redundant presence event, preceded by [`ScanEvent::Online`] if
+    /// the device was offline.
+    fn process(&mut self, event: ScanEvent) -> Vec<ScanEvent> {
+        match event {
+            ScanEvent::Online(p) => self.online(p).into_iter().collect(),
+            ScanEvent::Observation(obs) => {
+                let presence = Presence {
+                    datetime: obs.datetime,
+                    mac: obs.advertisement.mac(),
+                    adapter: obs.adapter.clone(),
+                };
+                let mut events: Vec<_> = self.online(presence).into_iter().collect();
+                self.observe(&obs);
+                events.push(ScanEvent::Observation(obs));
+                events
+            }
+            ScanEvent::Offline(p) => match self.devices.get_mut(&p.mac) {
+                Some(status) if status.online => {
+                    status.online = false;
+                    vec![ScanEvent::Offline(p)]
+                }
+                _ => vec![],
+            },
+            event => vec![event],
+        }
+    }
+
+    /// Marks the devices that have been silent for too long at `now` offline.
+    fn expire(&mut self, now: DateTime<Utc>) -> Vec<ScanEvent> {
+        let mut events = vec![];
+        for status in self.devices.values_mut() {
+            let expired = status
+                .deadline(self.offline_factor)
+                .is_some_and(|d| d < now);
+            if status.online && expired {
+                status.online = false;
+                events.push(ScanEvent::Offline(Presence {
+                    datetime: now,
+                    mac: status.mac,
+                    adapter: status.adapter.clone(),
+                }));
+            }
+        }
+        events
+    }
+
+    /// Earliest time at which a device currently online would go offline.
+    fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        self.devices
+            .values()
+            .filter(|s| s.online)
+            .filter_map(|s| s.deadline(self.offline_factor))
+            .min()
+    }
+}
+
+/// Print the status of the devices seen while scanning. See [`get_devices`].
+#[tokio::main(flavor = "current_thread")]
+pub async fn print_devices(opts: &ScanOptions) -> Res<()> {
+    for status in get_devices(opts).await? {
+        println!("{}", status);
+    }
+    Ok(())
+}
+
+/// Scan for `opts.timeout` and return the status of every device seen.
+pub async fn get_devices(opts: &ScanOptions) -> Res<Vec<DeviceStatus>> {
+    let timeout = opts
+        .timeout
+        .ok_or("timeout is required for listing devices")?;
+    let deadline = Instant::now() + timeout;
+    let events = scan(opts).await?;
+    pin_mut!(events);
+    let mut tracker = PresenceTracker::new(opts.offline_factor);
+    loop {
+        tokio::select! {
+            event = events.next() => {
+                tracker.process(event.ok_or("unexpected end of events")??);
+            }
+            _ = tokio::time::sleep_until(deadline) => break,
+        }
+    }
+    tracker.expire(Utc::now());
+    Ok(tracker.devices().cloned().collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruuvi::{test_advertisement, test_observation};
+
+    fn observation(secs: i64) -> ScanEvent {
+        let adv = test_advertisement([1; 6], secs as u16, 20.0);
+        ScanEvent::Observation(test_observation(secs, adv))
+    }
+
+    #[test]
+    fn offline_after_silence() {
+        let ts0 = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut tracker = PresenceTracker::new(5.0);
+        // first observation is preceded by an online event
+        assert_eq!(tracker.process(observation(0)).len(), 2);
+        assert!(tracker.next_deadline().is_none());
+        assert_eq!(tracker.process(observation(2)).len(), 1);
+        assert_eq!(tracker.process(observation(4)).len(), 1);
+        let status = tracker.devices().next().unwrap();
+        assert_eq!(status.interval, Some(2.0));
+        assert_eq!(status.advertisements, 3);
+        let deadline = ts0 + chrono::Duration::seconds(14);
+        assert_eq!(tracker.next_deadline(), Some(deadline));
+        assert!(tracker.expire(deadline).is_empty());
+        let events = tracker.expire(deadline + chrono::Duration::seconds(1));
+        assert!(matches!(events.as_slice(), [ScanEvent::Offline(_)]));
+        assert!(!tracker.devices().next().unwrap().online);
+        assert!(tracker.next_deadline().is_none());
+        // the gap while offline does not count as an interval
+        let events = tracker.process(observation(60));
+        assert!(matches!(events.as_slice(), [ScanEvent::Online(_), _]));
+        assert_eq!(tracker.devices().next().unwrap().interval, Some(2.0));
+    }
+}
@@ -1,7 +1,15 @@
+pub(crate) use advertisement::ser_mac;
+#[cfg(test)]
+pub(crate) use advertisement::test_advertisement;
 pub use advertisement::Advertisement;
-pub use measurement::{datetime_from_bytes, datetime_to_bytes, Measurement};
-pub use record::Record;
+pub use measurement::{datetime_from_bytes, datetime_to_bytes, Measurement, Sensor};
+#[cfg(test)]
+pub(crate) use observation::test_observation;
+pub use observation::Observation;
+pub(crate) use record::ser_dt;
+pub use record::{Record, RecordBuilder, TaggedRecord};
 
 mod advertisement;
 mod measurement;
+mod observation;
 mod record;
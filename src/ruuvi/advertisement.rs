@@ -5,7 +5,7 @@ use macaddr::MacAddr6;
 use serde::{Serialize, Serializer};
 use std::slice::Iter;
 
-#[derive(Debug, PartialEq, Serialize)]
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Advertisement {
     pub temperature: f64,
     pub humidity: f64,
@@ -19,7 +19,7 @@ pub struct Advertisement {
     pub mac: MacAddr6,
 }
 
-fn ser_mac<S: Serializer>(mac: &MacAddr6, s: S) -> Result<S::Ok, S::Error> {
+pub(crate) fn ser_mac<S: Serializer>(mac: &MacAddr6, s: S) -> Result<S::Ok, S::Error> {
     s.serialize_str(&mac.to_string())
 }
 
@@ -80,6 +80,27 @@ impl Advertisement {
     }
 }
 
+/// Advertisement for tests with typical values for the measurements that are
+/// not given.
+#[cfg(test)]
+pub(crate) fn test_advertisement(
+    mac: [u8; 6],
+    measurement: u16,
+    temperature: f64,
+) -> Advertisement {
+    Advertisement {
+        temperature,
+        humidity: 40.0,
+        air_pressure: 100000,
+        acceleration: [0.0, 0.0, 1.0],
+        voltage: 3.0,
+        tx_power: 4,
+        movement: 0,
+        measurement,
+        mac: MacAddr6::from(mac),
+    }
+}
+
 impl std::fmt::Display for Advertisement {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         // to_string really should not fail...
@@ -1,5 +1,41 @@
-use crate::err::Res;
+use crate::err::{Error, Res};
 use chrono::{DateTime, Utc};
+use std::str::FromStr;
+
+/// Sensor whose log is requested, `All` for all of them at once.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Sensor {
+    All,
+    Temperature,
+    Humidity,
+    AirPressure,
+}
+
+impl Sensor {
+    /// Endpoint of the sensor in the ruuvi protocol.
+    pub fn endpoint(self) -> u8 {
+        match self {
+            Self::All => 0x3A,
+            Self::Temperature => 0x30,
+            Self::Humidity => 0x31,
+            Self::AirPressure => 0x32,
+        }
+    }
+}
+
+impl FromStr for Sensor {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "all" => Ok(Self::All),
+            "temperature" => Ok(Self::Temperature),
+            "humidity" => Ok(Self::Humidity),
+            "air_pressure" => Ok(Self::AirPressure),
+            _ => Err(Error::Parse(format!("invalid sensor {}", s))),
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub enum Measurement {
@@ -19,21 +55,28 @@ impl Measurement {
         }
     }
 
+    pub fn datetime(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Temp(ts, _) | Self::Hum(ts, _) | Self::AirPres(ts, _) => Some(*ts),
+            Self::EndOfMeasurements => None,
+        }
+    }
+
     pub fn from_bytes(obs: impl AsRef<[u8]>) -> Res<Self> {
         let obs = obs.as_ref();
         if obs.len() != 11 {
             Err(format!("payload length should be 11 (was {})", obs.len()))?
         }
-        if obs[0] != 0x3A {
+        if !matches!(obs[0], 0x30..=0x32 | 0x3A) {
             Err(format!(
-                "header should start with 0x3A (was 0x{:x})",
+                "header should start with a sensor endpoint (was 0x{:x})",
                 obs[0]
             ))?
         }
         if obs[2] != 0x10 {
             Err(format!("action should be read (0x10, was 0x{:x})", obs[0]))?
         }
-        if obs[1] == 0x3A && obs[3..11] == [0xFF; 8] {
+        if obs[0] == obs[1] && obs[3..11] == [0xFF; 8] {
             return Ok(Measurement::EndOfMeasurements);
         }
         let ts = datetime_from_bytes([obs[3], obs[4], obs[5], obs[6]])?;
@@ -75,4 +118,20 @@ mod tests {
         let meas_exp = Measurement::Temp(ts, 1.33);
         assert_eq!(Measurement::from_bytes(meas_data).unwrap(), meas_exp)
     }
+
+    #[test]
+    fn single_sensor_measurements() {
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let meas_data = [
+            &[0x31, 0x31, 0x10],
+            datetime_to_bytes(ts).unwrap().as_slice(),
+            &[0x00, 0x00, 0x0F, 0xA0],
+        ]
+        .concat();
+        let meas_exp = Measurement::Hum(ts, 40.0);
+        assert_eq!(Measurement::from_bytes(meas_data).unwrap(), meas_exp);
+        let end_data = [[0x31, 0x31, 0x10].as_slice(), &[0xFF; 8]].concat();
+        let end = Measurement::from_bytes(end_data).unwrap();
+        assert_eq!(end, Measurement::EndOfMeasurements);
+    }
 }
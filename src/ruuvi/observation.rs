@@ -0,0 +1,63 @@
+use super::record::ser_dt;
+use super::Advertisement;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+/// Advertisement together with when, by which adapter and with what signal
+/// strength it was received.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Observation {
+    #[serde(serialize_with = "ser_dt")]
+    pub datetime: DateTime<Utc>,
+    #[serde(flatten)]
+    pub advertisement: Advertisement,
+    pub adapter: String,
+    pub rssi: Option<i16>,
+}
+
+impl std::fmt::Display for Observation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // to_string really should not fail...
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+/// Observation for tests, received by hci0 `secs` seconds after
+/// 2023-11-14T22:13:20Z.
+#[cfg(test)]
+pub(crate) fn test_observation(secs: i64, advertisement: Advertisement) -> Observation {
+    Observation {
+        datetime: DateTime::<Utc>::from_timestamp(1_700_000_000 + secs, 0).unwrap(),
+        advertisement,
+        adapter: String::from("hci0"),
+        rssi: Some(-60),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use macaddr::MacAddr6;
+
+    #[test]
+    fn observation_json() {
+        let obs = Observation {
+            datetime: DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap(),
+            advertisement: Advertisement {
+                temperature: 20.5,
+                humidity: 40.0,
+                air_pressure: 100000,
+                acceleration: [0.0, 0.0, 1.0],
+                voltage: 3.0,
+                tx_power: 4,
+                movement: 1,
+                measurement: 2,
+                mac: MacAddr6::from([0xcb, 0xb8, 0x33, 0x4c, 0x88, 0x4f]),
+            },
+            adapter: String::from("hci1"),
+            rssi: Some(-70),
+        };
+        let json_exp = r#"{"datetime":"2023-11-14T22:13:20+00:00","temperature":20.5,"humidity":40.0,"air_pressure":100000,"acceleration":[0.0,0.0,1.0],"voltage":3.0,"tx_power":4,"movement":1,"measurement":2,"mac":"CB:B8:33:4C:88:4F","adapter":"hci1","rssi":-70}"#;
+        assert_eq!(obs.to_string(), json_exp);
+    }
+}
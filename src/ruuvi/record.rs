@@ -1,15 +1,22 @@
+use super::advertisement::ser_mac;
 use super::Measurement;
 use crate::err::{Error, Res};
 use chrono::{DateTime, Utc};
+use macaddr::MacAddr6;
 use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
 
-#[derive(Debug, PartialEq, Serialize)]
+/// Log record, measurements missing from the log are `None`.
+#[derive(Clone, Debug, PartialEq, Serialize)]
 pub struct Record {
     #[serde(serialize_with = "ser_dt")]
     pub datetime: DateTime<Utc>,
-    pub temperature: f64,
-    pub humidity: f64,
-    pub air_pressure: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub humidity: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub air_pressure: Option<u32>,
 }
 
 impl std::fmt::Display for Record {
@@ -19,7 +26,23 @@ impl std::fmt::Display for Record {
     }
 }
 
-fn ser_dt<S: Serializer>(dt: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
+/// Log record together with the mac address of the device it came from.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TaggedRecord {
+    #[serde(serialize_with = "ser_mac")]
+    pub mac: MacAddr6,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+impl std::fmt::Display for TaggedRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // to_string really should not fail...
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+pub(crate) fn ser_dt<S: Serializer>(dt: &DateTime<Utc>, s: S) -> Result<S::Ok, S::Error> {
     s.serialize_str(&dt.to_rfc3339())
 }
 
@@ -30,6 +53,85 @@ impl Record {
             v => Err(format!("Invalid chunk of size {}", v.len()))?,
         }
     }
+
+    fn empty(datetime: DateTime<Utc>) -> Self {
+        Self {
+            datetime,
+            temperature: None,
+            humidity: None,
+            air_pressure: None,
+        }
+    }
+
+    /// Number of missing measurements.
+    pub fn missing(&self) -> usize {
+        [
+            self.temperature.is_none(),
+            self.humidity.is_none(),
+            self.air_pressure.is_none(),
+        ]
+        .into_iter()
+        .filter(|&m| m)
+        .count()
+    }
+
+    // keeps the first value if the measurement is duplicated
+    fn add(&mut self, measurement: Measurement) {
+        match measurement {
+            Measurement::Temp(_, t) => _ = self.temperature.get_or_insert(t),
+            Measurement::Hum(_, h) => _ = self.humidity.get_or_insert(h),
+            Measurement::AirPres(_, a) => _ = self.air_pressure.get_or_insert(a),
+            Measurement::EndOfMeasurements => {}
+        }
+    }
+}
+
+impl TryFrom<Measurement> for Record {
+    type Error = Error;
+
+    /// Partial record with a single measurement.
+    fn try_from(value: Measurement) -> Res<Self> {
+        let ts = value
+            .datetime()
+            .ok_or("end of measurements is not a record")?;
+        let mut record = Self::empty(ts);
+        record.add(value);
+        Ok(record)
+    }
+}
+
+const MAX_PENDING: usize = 8;
+
+/// Combines log measurements into records by their timestamps.
+///
+/// A record is returned as soon as all of its measurements have been received,
+/// regardless of the order they arrive in. Incomplete records are returned
+/// (oldest first) when more than `MAX_PENDING` of them are waiting, and the
+/// remaining ones with [`RecordBuilder::pop`] after the end of the log.
+#[derive(Debug, Default)]
+pub struct RecordBuilder {
+    pending: BTreeMap<DateTime<Utc>, Record>,
+}
+
+impl RecordBuilder {
+    /// Add a measurement, returns a record if one was completed or evicted.
+    pub fn push(&mut self, measurement: Measurement) -> Option<Record> {
+        let ts = measurement.datetime()?;
+        let record = self.pending.entry(ts).or_insert_with(|| Record::empty(ts));
+        record.add(measurement);
+        if record.missing() == 0 {
+            return self.pending.remove(&ts);
+        }
+        if self.pending.len() > MAX_PENDING {
+            return self.pop();
+        }
+        None
+    }
+
+    /// Remove the oldest pending (incomplete) record.
+    pub fn pop(&mut self) -> Option<Record> {
+        self.pending.pop_first().map(|(_, r)| r)
+    }
 }
 
 impl TryFrom<(&Measurement, &Measurement, &Measurement)> for Record {
@@ -62,9 +164,9 @@ impl TryFrom<(&Measurement, &Measurement, &Measurement)> for Record {
         }
         Ok(Record {
             datetime: *tst,
-            temperature: *t,
-            humidity: *h,
-            air_pressure: *a,
+            temperature: Some(*t),
+            humidity: Some(*h),
+            air_pressure: Some(*a),
         })
     }
 }
@@ -84,11 +186,65 @@ mod tests {
         let air_pres = Measurement::AirPres(datetime, air_pressure);
         let log_exp = Record {
             datetime,
-            temperature,
-            humidity,
-            air_pressure,
+            temperature: Some(temperature),
+            humidity: Some(humidity),
+            air_pressure: Some(air_pressure),
         };
         let log = Record::try_from((&temp, &hum, &air_pres)).unwrap();
         assert_eq!(log, log_exp)
     }
+
+    #[test]
+    fn builder_groups_by_timestamp() {
+        let ts0 = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let ts1 = ts0 + chrono::Duration::minutes(5);
+        let mut builder = RecordBuilder::default();
+        assert!(builder.push(Measurement::Temp(ts0, 15.0)).is_none());
+        assert!(builder.push(Measurement::Hum(ts1, 41.0)).is_none());
+        assert!(builder.push(Measurement::Hum(ts0, 40.0)).is_none());
+        assert!(builder.push(Measurement::AirPres(ts1, 10001)).is_none());
+        assert!(builder.push(Measurement::Temp(ts1, 16.0)).is_some());
+        assert!(builder.push(Measurement::AirPres(ts0, 10000)).is_some());
+        assert!(builder.pop().is_none());
+    }
+
+    #[test]
+    fn tagged_record_json() {
+        let record = TaggedRecord {
+            mac: MacAddr6::from([0xcb, 0xb8, 0x33, 0x4c, 0x88, 0x4f]),
+            record: Record {
+                datetime: DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap(),
+                temperature: Some(15.0),
+                humidity: None,
+                air_pressure: Some(10000),
+            },
+        };
+        let json_exp = r#"{"mac":"CB:B8:33:4C:88:4F","datetime":"2023-11-14T22:13:20+00:00","temperature":15.0,"air_pressure":10000}"#;
+        assert_eq!(record.to_string(), json_exp);
+    }
+
+    #[test]
+    fn builder_emits_partial_records() {
+        let ts0 = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let mut builder = RecordBuilder::default();
+        // humidity for ts0 dropped
+        assert!(builder.push(Measurement::Temp(ts0, 15.0)).is_none());
+        assert!(builder.push(Measurement::AirPres(ts0, 10000)).is_none());
+        let partial_exp = Record {
+            datetime: ts0,
+            temperature: Some(15.0),
+            humidity: None,
+            air_pressure: Some(10000),
+        };
+        for i in 1..MAX_PENDING as i64 {
+            let ts = ts0 + chrono::Duration::minutes(5 * i);
+            assert!(builder.push(Measurement::Temp(ts, 15.0)).is_none());
+        }
+        let ts = ts0 + chrono::Duration::minutes(5 * MAX_PENDING as i64);
+        let partial = builder.push(Measurement::Temp(ts, 15.0)).unwrap();
+        assert_eq!(partial, partial_exp);
+        assert_eq!(partial.missing(), 1);
+        let n_pending = std::iter::from_fn(|| builder.pop()).count();
+        assert_eq!(n_pending, MAX_PENDING);
+    }
 }
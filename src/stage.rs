@@ -0,0 +1,55 @@
+use crate::advertisements::ScanEvent;
+use crate::err::Res;
+use chrono::{DateTime, Utc};
+use futures::{future, stream, Stream, StreamExt};
+use std::collections::VecDeque;
+use tokio::time::Instant;
+
+/// Stateful processing step for the events from [`scan`](crate::scan), see
+/// [`apply`].
+pub trait Stage {
+    /// Items passed on, usually events.
+    type Output;
+
+    /// Items to pass on after receiving `event`.
+    fn process(&mut self, event: ScanEvent) -> Vec<Self::Output>;
+
+    /// Items to pass on at `now`, called once [`Stage::next_deadline`] has
+    /// passed.
+    fn expire(&mut self, now: DateTime<Utc>) -> Vec<Self::Output>;
+
+    /// Next time [`Stage::expire`] should be called, if any.
+    fn next_deadline(&self) -> Option<DateTime<Utc>>;
+}
+
+/// Pass `events` through `stage`. Errors are passed on as is.
+pub fn apply<S: Stage>(
+    events: impl Stream<Item = Res<ScanEvent>> + Unpin,
+    stage: S,
+) -> impl Stream<Item = Res<S::Output>> {
+    let state = (events, stage, VecDeque::new());
+    stream::unfold(state, |(mut events, mut stage, mut queue)| async move {
+        loop {
+            if let Some(event) = queue.pop_front() {
+                return Some((Ok(event), (events, stage, queue)));
+            }
+            let deadline = stage.next_deadline();
+            let expiry = async {
+                match deadline {
+                    Some(d) => {
+                        let wait = (d - Utc::now()).to_std().unwrap_or_default();
+                        tokio::time::sleep_until(Instant::now() + wait).await
+                    }
+                    None => future::pending().await,
+                }
+            };
+            tokio::select! {
+                event = events.next() => match event? {
+                    Ok(event) => queue.extend(stage.process(event)),
+                    Err(e) => return Some((Err(e), (events, stage, queue))),
+                },
+                _ = expiry => queue.extend(stage.expire(Utc::now())),
+            }
+        }
+    })
+}
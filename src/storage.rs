@@ -0,0 +1,456 @@
+use crate::aggregate::Summary;
+use crate::err::Res;
+use crate::ruuvi::{Advertisement, Record};
+use chrono::{DateTime, Utc};
+use macaddr::MacAddr6;
+use rusqlite::{params, Connection, Row};
+use std::path::Path;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS record (
+    mac TEXT NOT NULL,
+    datetime INTEGER NOT NULL,
+    temperature REAL,
+    humidity REAL,
+    air_pressure INTEGER,
+    PRIMARY KEY (mac, datetime)
+);
+CREATE TABLE IF NOT EXISTS advertisement (
+    mac TEXT NOT NULL,
+    datetime INTEGER NOT NULL,
+    temperature REAL NOT NULL,
+    humidity REAL NOT NULL,
+    air_pressure INTEGER NOT NULL,
+    acceleration_x REAL NOT NULL,
+    acceleration_y REAL NOT NULL,
+    acceleration_z REAL NOT NULL,
+    voltage REAL NOT NULL,
+    tx_power INTEGER NOT NULL,
+    movement INTEGER NOT NULL,
+    measurement INTEGER NOT NULL,
+    PRIMARY KEY (mac, datetime)
+);
+CREATE TABLE IF NOT EXISTS summary (
+    mac TEXT NOT NULL,
+    datetime INTEGER NOT NULL,
+    temperature REAL NOT NULL,
+    humidity REAL NOT NULL,
+    air_pressure INTEGER NOT NULL,
+    temperature_min REAL NOT NULL,
+    temperature_max REAL NOT NULL,
+    humidity_min REAL NOT NULL,
+    humidity_max REAL NOT NULL,
+    air_pressure_min INTEGER NOT NULL,
+    air_pressure_max INTEGER NOT NULL,
+    count INTEGER NOT NULL,
+    voltage REAL NOT NULL,
+    PRIMARY KEY (mac, datetime)
+);
+";
+
+/// Measurement history stored in a SQLite database.
+///
+/// Records, advertisements and summaries are keyed on (mac, datetime), so
+/// inserting the same observation again overwrites the existing row instead of
+/// duplicating it. Missing measurements of a partial record do not overwrite
+/// stored values. Only the log records (not the summaries of the
+/// advertisements) are used to decide where [`sync_log`](crate::sync_log)
+/// resumes.
+pub struct Storage {
+    conn: Connection,
+}
+
+impl Storage {
+    /// Open (and create if necessary) the database at `path`.
+    pub fn open(path: impl AsRef<Path>) -> Res<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    /// Open a database that only lives in memory.
+    pub fn open_in_memory() -> Res<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Res<Self> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Insert log records from device `mac`, returns the number of rows written.
+    pub fn insert_records<'a>(
+        &mut self,
+        mac: MacAddr6,
+        records: impl IntoIterator<Item = &'a Record>,
+    ) -> Res<usize> {
+        let tx = self.conn.transaction()?;
+        let mut n = 0;
+        {
+            let mut stmt = tx.prepare_cached(
+                "INSERT INTO record (mac, datetime, temperature, humidity, air_pressure)
+                 VALUES (?1, ?2, ?3, ?4, ?5)
+                 ON CONFLICT (mac, datetime) DO UPDATE SET
+                    temperature = COALESCE(excluded.temperature, temperature),
+                    humidity = COALESCE(excluded.humidity, humidity),
+                    air_pressure = COALESCE(excluded.air_pressure, air_pressure)",
+            )?;
+            for r in records {
+                n += stmt.execute(params![
+                    mac.to_string(),
+                    r.datetime.timestamp_millis(),
+                    r.temperature,
+                    r.humidity,
+                    r.air_pressure,
+                ])?;
+            }
+        }
+        tx.commit()?;
+        Ok(n)
+    }
+
+    /// Datetime of the newest stored record from device `mac`.
+    pub fn latest_record(&self, mac: MacAddr6) -> Res<Option<DateTime<Utc>>> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT MAX(datetime) FROM record WHERE mac = ?1")?;
+        let latest = stmt.query_row(params![mac.to_string()], |row| {
+            row.get::<_, Option<i64>>(0)?
+                .map(|_| datetime_from_row(row, 0))
+                .transpose()
+        })?;
+        Ok(latest)
+    }
+
+    /// Insert an advertisement received at `datetime`.
+    pub fn insert_advertisement(&self, datetime: DateTime<Utc>, adv: &Advertisement) -> Res<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO advertisement (mac, datetime, temperature, humidity, air_pressure,
+                acceleration_x, acceleration_y, acceleration_z, voltage, tx_power, movement,
+                measurement)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)
+             ON CONFLICT (mac, datetime) DO UPDATE SET
+                temperature = excluded.temperature,
+                humidity = excluded.humidity,
+                air_pressure = excluded.air_pressure,
+                acceleration_x = excluded.acceleration_x,
+                acceleration_y = excluded.acceleration_y,
+                acceleration_z = excluded.acceleration_z,
+                voltage = excluded.voltage,
+                tx_power = excluded.tx_power,
+                movement = excluded.movement,
+                measurement = excluded.measurement",
+        )?;
+        stmt.execute(params![
+            adv.mac.to_string(),
+            datetime.timestamp_millis(),
+            adv.temperature,
+            adv.humidity,
+            adv.air_pressure,
+            adv.acceleration[0],
+            adv.acceleration[1],
+            adv.acceleration[2],
+            adv.voltage,
+            adv.tx_power,
+            adv.movement,
+            adv.measurement,
+        ])?;
+        Ok(())
+    }
+
+    /// Insert a summary of the advertisements from a device.
+    pub fn insert_summary(&self, summary: &Summary) -> Res<()> {
+        let mut stmt = self.conn.prepare_cached(
+            "INSERT INTO summary (mac, datetime, temperature, humidity, air_pressure,
+                temperature_min, temperature_max, humidity_min, humidity_max,
+                air_pressure_min, air_pressure_max, count, voltage)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)
+             ON CONFLICT (mac, datetime) DO UPDATE SET
+                temperature = excluded.temperature,
+                humidity = excluded.humidity,
+                air_pressure = excluded.air_pressure,
+                temperature_min = excluded.temperature_min,
+                temperature_max = excluded.temperature_max,
+                humidity_min = excluded.humidity_min,
+                humidity_max = excluded.humidity_max,
+                air_pressure_min = excluded.air_pressure_min,
+                air_pressure_max = excluded.air_pressure_max,
+                count = excluded.count,
+                voltage = excluded.voltage",
+        )?;
+        let r = &summary.record;
+        stmt.execute(params![
+            summary.mac.to_string(),
+            r.datetime.timestamp_millis(),
+            r.temperature,
+            r.humidity,
+            r.air_pressure,
+            summary.temperature_min,
+            summary.temperature_max,
+            summary.humidity_min,
+            summary.humidity_max,
+            summary.air_pressure_min,
+            summary.air_pressure_max,
+            summary.count,
+            summary.voltage,
+        ])?;
+        Ok(())
+    }
+
+    /// Records from device `mac` with `from <= datetime < to`, ordered by datetime.
+    pub fn records(
+        &self,
+        mac: MacAddr6,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Res<Vec<Record>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT datetime, temperature, humidity, air_pressure FROM record
+             WHERE mac = ?1 AND datetime >= ?2 AND datetime < ?3
+             ORDER BY datetime",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                mac.to_string(),
+                from.timestamp_millis(),
+                to.timestamp_millis()
+            ],
+            record_from_row,
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Advertisements from device `mac` with `from <= datetime < to`, ordered by
+    /// datetime.
+    pub fn advertisements(
+        &self,
+        mac: MacAddr6,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Res<Vec<(DateTime<Utc>, Advertisement)>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT datetime, temperature, humidity, air_pressure, acceleration_x,
+                acceleration_y, acceleration_z, voltage, tx_power, movement, measurement
+             FROM advertisement
+             WHERE mac = ?1 AND datetime >= ?2 AND datetime < ?3
+             ORDER BY datetime",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                mac.to_string(),
+                from.timestamp_millis(),
+                to.timestamp_millis()
+            ],
+            |row| {
+                Ok((
+                    datetime_from_row(row, 0)?,
+                    advertisement_from_row(row, mac)?,
+                ))
+            },
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+
+    /// Summaries from device `mac` with `from <= datetime < to`, ordered by
+    /// datetime.
+    pub fn summaries(
+        &self,
+        mac: MacAddr6,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> Res<Vec<Summary>> {
+        let mut stmt = self.conn.prepare_cached(
+            "SELECT datetime, temperature, humidity, air_pressure, temperature_min,
+                temperature_max, humidity_min, humidity_max, air_pressure_min,
+                air_pressure_max, count, voltage
+             FROM summary
+             WHERE mac = ?1 AND datetime >= ?2 AND datetime < ?3
+             ORDER BY datetime",
+        )?;
+        let rows = stmt.query_map(
+            params![
+                mac.to_string(),
+                from.timestamp_millis(),
+                to.timestamp_millis()
+            ],
+            |row| summary_from_row(row, mac),
+        )?;
+        Ok(rows.collect::<Result<_, _>>()?)
+    }
+}
+
+fn datetime_from_row(row: &Row, idx: usize) -> rusqlite::Result<DateTime<Utc>> {
+    let millis: i64 = row.get(idx)?;
+    let nsecs = millis.rem_euclid(1000) as u32 * 1_000_000;
+    DateTime::<Utc>::from_timestamp(millis.div_euclid(1000), nsecs)
+        .ok_or(rusqlite::Error::IntegralValueOutOfRange(idx, millis))
+}
+
+fn record_from_row(row: &Row) -> rusqlite::Result<Record> {
+    Ok(Record {
+        datetime: datetime_from_row(row, 0)?,
+        temperature: row.get(1)?,
+        humidity: row.get(2)?,
+        air_pressure: row.get(3)?,
+    })
+}
+
+fn summary_from_row(row: &Row, mac: MacAddr6) -> rusqlite::Result<Summary> {
+    Ok(Summary {
+        mac,
+        record: record_from_row(row)?,
+        temperature_min: row.get(4)?,
+        temperature_max: row.get(5)?,
+        humidity_min: row.get(6)?,
+        humidity_max: row.get(7)?,
+        air_pressure_min: row.get(8)?,
+        air_pressure_max: row.get(9)?,
+        count: row.get(10)?,
+        voltage: row.get(11)?,
+    })
+}
+
+fn advertisement_from_row(row: &Row, mac: MacAddr6) -> rusqlite::Result<Advertisement> {
+    Ok(Advertisement {
+        temperature: row.get(1)?,
+        humidity: row.get(2)?,
+        air_pressure: row.get(3)?,
+        acceleration: [row.get(4)?, row.get(5)?, row.get(6)?],
+        voltage: row.get(7)?,
+        tx_power: row.get(8)?,
+        movement: row.get(9)?,
+        measurement: row.get(10)?,
+        mac,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn mac() -> MacAddr6 {
+        MacAddr6::from([0xcb, 0xb8, 0x33, 0x4c, 0x88, 0x4f])
+    }
+
+    fn record(datetime: DateTime<Utc>, temperature: f64) -> Record {
+        Record {
+            datetime,
+            temperature: Some(temperature),
+            humidity: Some(40.0),
+            air_pressure: Some(100044),
+        }
+    }
+
+    #[test]
+    fn records_are_upserted() {
+        let mut storage = Storage::open_in_memory().unwrap();
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let first = [record(ts, 15.0), record(ts + Duration::minutes(5), 16.0)];
+        let overlap = [
+            record(ts + Duration::minutes(5), 17.0),
+            record(ts + Duration::minutes(10), 18.0),
+        ];
+        storage.insert_records(mac(), &first).unwrap();
+        storage.insert_records(mac(), &overlap).unwrap();
+
+        let stored = storage.records(mac(), ts, ts + Duration::hours(1)).unwrap();
+        assert_eq!(
+            stored,
+            vec![first[0].clone(), overlap[0].clone(), overlap[1].clone()]
+        );
+    }
+
+    #[test]
+    fn partial_records_do_not_erase_values() {
+        let mut storage = Storage::open_in_memory().unwrap();
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let full = record(ts, 15.0);
+        let partial = Record {
+            humidity: None,
+            ..record(ts, 16.0)
+        };
+        storage.insert_records(mac(), [&full]).unwrap();
+        storage.insert_records(mac(), [&partial]).unwrap();
+
+        let stored = storage.records(mac(), ts, ts + Duration::hours(1)).unwrap();
+        let exp = Record {
+            humidity: full.humidity,
+            ..partial
+        };
+        assert_eq!(stored, vec![exp]);
+    }
+
+    #[test]
+    fn records_time_range() {
+        let mut storage = Storage::open_in_memory().unwrap();
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let records: Vec<_> = (0..5)
+            .map(|i| record(ts + Duration::minutes(i), i as f64))
+            .collect();
+        storage.insert_records(mac(), &records).unwrap();
+
+        let from = ts + Duration::minutes(1);
+        let to = ts + Duration::minutes(3);
+        assert_eq!(storage.records(mac(), from, to).unwrap(), records[1..3]);
+        let other = MacAddr6::from([0, 1, 2, 3, 4, 5]);
+        assert!(storage.records(other, from, to).unwrap().is_empty());
+    }
+
+    #[test]
+    fn latest_record() {
+        let mut storage = Storage::open_in_memory().unwrap();
+        assert_eq!(storage.latest_record(mac()).unwrap(), None);
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let records = [record(ts + Duration::minutes(5), 16.0), record(ts, 15.0)];
+        storage.insert_records(mac(), &records).unwrap();
+        let latest = ts + Duration::minutes(5);
+        assert_eq!(storage.latest_record(mac()).unwrap(), Some(latest));
+    }
+
+    #[test]
+    fn advertisement_roundtrip() {
+        let storage = Storage::open_in_memory().unwrap();
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000, 123_000_000).unwrap();
+        let adv = Advertisement {
+            temperature: 24.3,
+            air_pressure: 100044,
+            humidity: 53.49,
+            acceleration: [0.004, -0.004, 1.036],
+            tx_power: 4,
+            voltage: 2.977,
+            movement: 66,
+            measurement: 205,
+            mac: mac(),
+        };
+        storage.insert_advertisement(ts, &adv).unwrap();
+        storage.insert_advertisement(ts, &adv).unwrap();
+
+        let stored = storage
+            .advertisements(mac(), ts, ts + Duration::seconds(1))
+            .unwrap();
+        assert_eq!(stored, vec![(ts, adv)]);
+    }
+
+    #[test]
+    fn summaries_are_separate() {
+        let storage = Storage::open_in_memory().unwrap();
+        let ts = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let summary = Summary {
+            mac: mac(),
+            record: record(ts, 20.5),
+            temperature_min: 20.0,
+            temperature_max: 21.0,
+            humidity_min: 39.0,
+            humidity_max: 41.0,
+            air_pressure_min: 100040,
+            air_pressure_max: 100050,
+            count: 30,
+            voltage: 2.977,
+        };
+        storage.insert_summary(&summary).unwrap();
+        storage.insert_summary(&summary).unwrap();
+        // the summaries do not affect where the log sync resumes
+        assert_eq!(storage.latest_record(mac()).unwrap(), None);
+        let to = ts + Duration::minutes(5);
+        assert_eq!(storage.summaries(mac(), ts, to).unwrap(), vec![summary]);
+    }
+}
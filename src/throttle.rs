@@ -0,0 +1,276 @@
+use crate::advertisements::ScanEvent;
+use crate::err::{Error, Res};
+use crate::ruuvi::{Advertisement, Observation};
+use crate::stage::Stage;
+use chrono::{DateTime, Utc};
+use macaddr::MacAddr6;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How the reading is chosen from the advertisements within an interval.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Reduce {
+    #[default]
+    Latest,
+    Mean,
+    Median,
+}
+
+impl FromStr for Reduce {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "latest" => Ok(Self::Latest),
+            "mean" => Ok(Self::Mean),
+            "median" => Ok(Self::Median),
+            _ => Err(Error::Parse(format!("invalid reduction {}", s))),
+        }
+    }
+}
+
+/// Minimum changes for a reading to be emitted, fields that are `None` are
+/// not compared.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Deltas {
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub air_pressure: Option<f64>,
+    pub voltage: Option<f64>,
+}
+
+impl FromStr for Deltas {
+    type Err = Error;
+
+    /// Comma-separated `field=delta` pairs, eg. `temperature=0.5,humidity=2`.
+    fn from_str(s: &str) -> Res<Self> {
+        let mut deltas = Self::default();
+        for pair in s.split(',') {
+            let (field, delta) = pair
+                .split_once('=')
+                .ok_or_else(|| Error::Parse(format!("invalid delta {}", pair)))?;
+            let delta = Some(delta.parse()?);
+            match field {
+                "temperature" => deltas.temperature = delta,
+                "humidity" => deltas.humidity = delta,
+                "air_pressure" => deltas.air_pressure = delta,
+                "voltage" => deltas.voltage = delta,
+                _ => Err(Error::Parse(format!("invalid field {}", field)))?,
+            }
+        }
+        Ok(deltas)
+    }
+}
+
+impl Deltas {
+    /// Whether any of the compared fields has changed at least by its delta.
+    fn changed(&self, prev: &Advertisement, next: &Advertisement) -> bool {
+        let changed =
+            |delta: Option<f64>, a: f64, b: f64| delta.is_some_and(|d| (a - b).abs() >= d);
+        changed(self.temperature, prev.temperature, next.temperature)
+            || changed(self.humidity, prev.humidity, next.humidity)
+            || changed(
+                self.air_pressure,
+                prev.air_pressure.into(),
+                next.air_pressure.into(),
+            )
+            || changed(self.voltage, prev.voltage, next.voltage)
+    }
+}
+
+/// Limits the rate of observations from each device.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Throttle {
+    /// At most one observation per device per interval, reduced from the
+    /// advertisements received within the interval. The interval starts from
+    /// the first advertisement.
+    Interval(Duration, Reduce),
+    /// Only observations that differ enough from the previous one emitted.
+    OnChange(Deltas),
+}
+
+/// Applies a [`Throttle`] to the observations, see [`Stage`]. Other events are
+/// passed on as is.
+#[derive(Debug)]
+pub struct Throttler {
+    throttle: Throttle,
+    // observations within the current interval
+    windows: HashMap<MacAddr6, (DateTime<Utc>, Vec<Observation>)>,
+    // previous observation emitted
+    emitted: HashMap<MacAddr6, Advertisement>,
+}
+
+impl Throttler {
+    pub fn new(throttle: Throttle) -> Self {
+        Self {
+            throttle,
+            windows: HashMap::new(),
+            emitted: HashMap::new(),
+        }
+    }
+
+    fn flush(&mut self, mac: &MacAddr6) -> Option<ScanEvent> {
+        let (_, observations) = self.windows.remove(mac)?;
+        let reduce = match self.throttle {
+            Throttle::Interval(_, reduce) => reduce,
+            Throttle::OnChange(_) => Reduce::Latest,
+        };
+        reduce_observations(observations, reduce).map(ScanEvent::Observation)
+    }
+}
+
+impl Stage for Throttler {
+    type Output = ScanEvent;
+
+    fn process(&mut self, event: ScanEvent) -> Vec<ScanEvent> {
+        match (&self.throttle, event) {
+            (Throttle::Interval(interval, _), ScanEvent::Observation(obs)) => {
+                let interval = chrono::Duration::milliseconds(interval.as_millis() as i64);
+                let end = obs.datetime + interval;
+                let mac = obs.advertisement.mac();
+                let window = self.windows.entry(mac).or_insert((end, vec![]));
+                window.1.push(obs);
+                vec![]
+            }
+            (Throttle::OnChange(deltas), ScanEvent::Observation(obs)) => {
+                let mac = obs.advertisement.mac();
+                let changed = match self.emitted.get(&mac) {
+                    Some(prev) => deltas.changed(prev, &obs.advertisement),
+                    None => true,
+                };
+                if !changed {
+                    return vec![];
+                }
+                self.emitted.insert(mac, obs.advertisement.clone());
+                vec![ScanEvent::Observation(obs)]
+            }
+            // the reading from the interval precedes the device going offline
+            (_, ScanEvent::Offline(p)) => {
+                let mut events: Vec<_> = self.flush(&p.mac).into_iter().collect();
+                events.push(ScanEvent::Offline(p));
+                events
+            }
+            (_, event) => vec![event],
+        }
+    }
+
+    fn expire(&mut self, now: DateTime<Utc>) -> Vec<ScanEvent> {
+        let mut ended: Vec<_> = self
+            .windows
+            .iter()
+            .filter(|(_, (end, _))| *end <= now)
+            .map(|(mac, (end, _))| (*end, *mac))
+            .collect();
+        ended.sort();
+        ended
+            .into_iter()
+            .filter_map(|(_, mac)| self.flush(&mac))
+            .collect()
+    }
+
+    fn next_deadline(&self) -> Option<DateTime<Utc>> {
+        self.windows.values().map(|(end, _)| *end).min()
+    }
+}
+
+/// The latest observation with the measurements (temperature, humidity, air
+/// pressure, acceleration and voltage) reduced over all of them.
+fn reduce_observations(mut observations: Vec<Observation>, reduce: Reduce) -> Option<Observation> {
+    let f = match reduce {
+        Reduce::Latest => return observations.pop(),
+        Reduce::Mean => mean,
+        Reduce::Median => median,
+    };
+    let field = |get: fn(&Advertisement) -> f64| {
+        let values: Vec<_> = observations.iter().map(|o| get(&o.advertisement)).collect();
+        f(values)
+    };
+    let temperature = field(|a| a.temperature);
+    let humidity = field(|a| a.humidity);
+    let air_pressure = field(|a| a.air_pressure.into());
+    let voltage = field(|a| a.voltage);
+    let acceleration = [
+        field(|a| a.acceleration[0]),
+        field(|a| a.acceleration[1]),
+        field(|a| a.acceleration[2]),
+    ];
+    let mut obs = observations.pop()?;
+    obs.advertisement.temperature = temperature;
+    obs.advertisement.humidity = humidity;
+    obs.advertisement.air_pressure = air_pressure.round() as u32;
+    obs.advertisement.voltage = voltage;
+    obs.advertisement.acceleration = acceleration;
+    Some(obs)
+}
+
+fn mean(values: Vec<f64>) -> f64 {
+    values.iter().sum::<f64>() / values.len() as f64
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    values.sort_by(f64::total_cmp);
+    let mid = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[mid - 1] + values[mid]) / 2.0,
+        _ => values[mid],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruuvi::{test_advertisement, test_observation};
+
+    fn observation(secs: i64, temperature: f64) -> ScanEvent {
+        let adv = test_advertisement([1; 6], secs as u16, temperature);
+        ScanEvent::Observation(test_observation(secs, adv))
+    }
+
+    fn temperature(event: &ScanEvent) -> f64 {
+        match event {
+            ScanEvent::Observation(obs) => obs.advertisement.temperature,
+            _ => panic!("not an observation"),
+        }
+    }
+
+    #[test]
+    fn interval() {
+        let ts0 = DateTime::<Utc>::from_timestamp(1_700_000_000, 0).unwrap();
+        let throttle = Throttle::Interval(Duration::from_secs(10), Reduce::Median);
+        let mut throttler = Throttler::new(throttle);
+        for (secs, t) in [(0, 20.0), (3, 25.0), (6, 21.0)] {
+            assert!(throttler.process(observation(secs, t)).is_empty());
+        }
+        let deadline = ts0 + chrono::Duration::seconds(10);
+        assert_eq!(throttler.next_deadline(), Some(deadline));
+        assert!(throttler
+            .expire(deadline - chrono::Duration::seconds(1))
+            .is_empty());
+        let events = throttler.expire(deadline);
+        assert_eq!(events.len(), 1);
+        assert_eq!(temperature(&events[0]), 21.0);
+        assert!(throttler.next_deadline().is_none());
+    }
+
+    #[test]
+    fn on_change() {
+        let deltas: Deltas = "temperature=0.5".parse().unwrap();
+        let mut throttler = Throttler::new(Throttle::OnChange(deltas));
+        assert_eq!(throttler.process(observation(0, 20.0)).len(), 1);
+        assert!(throttler.process(observation(1, 20.3)).is_empty());
+        assert!(throttler.process(observation(2, 19.6)).is_empty());
+        // compared to the previous emitted
+        assert_eq!(throttler.process(observation(3, 20.5)).len(), 1);
+        assert!(throttler.process(observation(4, 20.1)).is_empty());
+    }
+
+    #[test]
+    fn reductions() {
+        assert_eq!(mean(vec![1.0, 2.0, 6.0]), 3.0);
+        assert_eq!(median(vec![6.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(vec![6.0, 1.0, 2.0, 3.0]), 2.5);
+        assert!("pressure=1".parse::<Deltas>().is_err());
+        assert!("temperature".parse::<Deltas>().is_err());
+    }
+}
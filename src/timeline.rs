@@ -0,0 +1,186 @@
+use crate::ruuvi::{Record, TaggedRecord};
+use chrono::{DateTime, Utc};
+use macaddr::MacAddr6;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Source of a point in a merged timeline.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Origin {
+    /// Log downloaded from the device, see [`get_log`](crate::get_log).
+    #[default]
+    Log,
+    /// Aggregated advertisements, see [`aggregate`](crate::aggregate).
+    Scan,
+}
+
+/// Options for [`merge_timeline`].
+#[derive(Clone, Debug)]
+pub struct TimelineOptions {
+    /// Source preferred when both of them have points close to each other.
+    pub priority: Origin,
+    /// Points from the other source at most this far from a point of the
+    /// preferred source are dropped.
+    pub tolerance: Duration,
+}
+
+impl Default for TimelineOptions {
+    fn default() -> Self {
+        Self {
+            priority: Origin::Log,
+            // the logging interval of the devices
+            tolerance: Duration::from_secs(5 * 60),
+        }
+    }
+}
+
+/// Record in a merged timeline together with its source.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TimelinePoint {
+    #[serde(flatten)]
+    pub record: TaggedRecord,
+    pub origin: Origin,
+}
+
+impl std::fmt::Display for TimelinePoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // to_string really should not fail...
+        write!(f, "{}", serde_json::to_string(self).unwrap())
+    }
+}
+
+/// Merge the log records with the aggregated advertisements (eg. from
+/// [`Summary`](crate::Summary)) of each device into one timeline, ordered by
+/// mac and datetime.
+///
+/// Every point from the source with `opts.priority` is kept, while the points
+/// from the other source are only used to fill the gaps, ie. they are dropped
+/// if there is a preferred point within `opts.tolerance`. The points are not
+/// combined, so partial log records stay partial.
+pub fn merge_timeline(
+    logs: impl IntoIterator<Item = TaggedRecord>,
+    scans: impl IntoIterator<Item = TaggedRecord>,
+    opts: &TimelineOptions,
+) -> Vec<TimelinePoint> {
+    let mut devices: BTreeMap<MacAddr6, [Vec<Record>; 2]> = BTreeMap::new();
+    for r in logs {
+        devices.entry(r.mac).or_default()[0].push(r.record);
+    }
+    for r in scans {
+        devices.entry(r.mac).or_default()[1].push(r.record);
+    }
+    // practically unlimited if out of range
+    let tolerance =
+        chrono::Duration::from_std(opts.tolerance).unwrap_or(chrono::Duration::days(365_000));
+    let mut timeline = vec![];
+    for (mac, [logs, scans]) in devices {
+        let (preferred, other) = match opts.priority {
+            Origin::Log => ((logs, Origin::Log), (scans, Origin::Scan)),
+            Origin::Scan => ((scans, Origin::Scan), (logs, Origin::Log)),
+        };
+        let mut points = merge_device(preferred, other, tolerance);
+        points.sort_by_key(|(r, _)| r.datetime);
+        timeline.extend(points.into_iter().map(|(record, origin)| TimelinePoint {
+            record: TaggedRecord { mac, record },
+            origin,
+        }));
+    }
+    timeline
+}
+
+// preferred points followed by the other points that fill the gaps
+fn merge_device(
+    (preferred, preferred_origin): (Vec<Record>, Origin),
+    (other, other_origin): (Vec<Record>, Origin),
+    tolerance: chrono::Duration,
+) -> Vec<(Record, Origin)> {
+    let mut datetimes: Vec<_> = preferred.iter().map(|r| r.datetime).collect();
+    datetimes.sort();
+    let covered = |dt: DateTime<Utc>| {
+        // the closest preferred points before and after dt
+        let i = datetimes.partition_point(|p| *p < dt);
+        let before = i.checked_sub(1).and_then(|i| datetimes.get(i));
+        let mut closest = before.into_iter().chain(datetimes.get(i));
+        closest.any(|p| (*p - dt).abs() <= tolerance)
+    };
+    let gaps: Vec<_> = other.into_iter().filter(|r| !covered(r.datetime)).collect();
+    let preferred = preferred.into_iter().map(|r| (r, preferred_origin));
+    preferred
+        .chain(gaps.into_iter().map(|r| (r, other_origin)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(mac: u8, mins: i64, temperature: f64) -> TaggedRecord {
+        TaggedRecord {
+            mac: MacAddr6::from([mac; 6]),
+            record: Record {
+                datetime: DateTime::<Utc>::from_timestamp(1_700_000_000 + mins * 60, 0).unwrap(),
+                temperature: Some(temperature),
+                humidity: None,
+                air_pressure: None,
+            },
+        }
+    }
+
+    fn summary(timeline: &[TimelinePoint]) -> Vec<(u8, f64, Origin)> {
+        timeline
+            .iter()
+            .map(|p| {
+                let mac = p.record.mac.as_bytes()[0];
+                (mac, p.record.record.temperature.unwrap(), p.origin)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fill_gaps() {
+        // log is missing between 10 and 30 minutes
+        let logs = [0, 5, 10, 30].map(|m| record(1, m, 1.0));
+        let scans = [2, 7, 17, 22, 32, 40].map(|m| record(1, m, 2.0));
+        let timeline = merge_timeline(logs, scans, &TimelineOptions::default());
+        let minutes: Vec<_> = timeline
+            .iter()
+            .map(|p| (p.record.record.datetime.timestamp() - 1_700_000_000) / 60)
+            .collect();
+        assert_eq!(minutes, vec![0, 5, 10, 17, 22, 30, 40]);
+        assert_eq!(timeline[3].origin, Origin::Scan);
+        assert_eq!(timeline[5].origin, Origin::Log);
+    }
+
+    #[test]
+    fn priority_and_devices() {
+        let logs = [record(2, 0, 1.0), record(1, 0, 1.0), record(1, 20, 1.0)];
+        let scans = [record(1, 1, 2.0), record(2, 30, 2.0)];
+        let opts = TimelineOptions {
+            priority: Origin::Scan,
+            tolerance: Duration::from_secs(120),
+        };
+        let timeline = merge_timeline(logs, scans, &opts);
+        let exp = vec![
+            (1, 2.0, Origin::Scan),
+            (1, 1.0, Origin::Log),
+            (2, 1.0, Origin::Log),
+            (2, 2.0, Origin::Scan),
+        ];
+        assert_eq!(summary(&timeline), exp);
+    }
+
+    #[test]
+    fn point_json() {
+        let point = TimelinePoint {
+            record: record(1, 0, 20.5),
+            origin: Origin::Scan,
+        };
+        let exp = concat!(
+            r#"{"mac":"01:01:01:01:01:01","datetime":"2023-11-14T22:13:20+00:00","#,
+            r#""temperature":20.5,"origin":"scan"}"#
+        );
+        assert_eq!(point.to_string(), exp);
+    }
+}
@@ -0,0 +1,243 @@
+use crate::err::{Error, Res};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::str::FromStr;
+
+const KELVIN_OFFSET: f64 = 273.15;
+const PA_PER_INHG: f64 = 3386.389;
+const PA_PER_MMHG: f64 = 133.322387415;
+const STANDARD_GRAVITY: f64 = 9.80665;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum TemperatureUnit {
+    #[default]
+    Celsius,
+    Fahrenheit,
+    Kelvin,
+}
+
+impl FromStr for TemperatureUnit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "c" => Ok(Self::Celsius),
+            "f" => Ok(Self::Fahrenheit),
+            "k" => Ok(Self::Kelvin),
+            _ => Err(Error::Parse(format!("invalid temperature unit {}", s))),
+        }
+    }
+}
+
+impl TemperatureUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Celsius => "°C",
+            Self::Fahrenheit => "°F",
+            Self::Kelvin => "K",
+        }
+    }
+
+    /// Convert from degrees Celsius.
+    pub fn convert(&self, celsius: f64) -> f64 {
+        match self {
+            Self::Celsius => celsius,
+            Self::Fahrenheit => celsius * 1.8 + 32.0,
+            Self::Kelvin => celsius + KELVIN_OFFSET,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PressureUnit {
+    #[default]
+    Pascal,
+    Hectopascal,
+    InchOfMercury,
+    MillimeterOfMercury,
+}
+
+impl FromStr for PressureUnit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "pa" => Ok(Self::Pascal),
+            "hpa" => Ok(Self::Hectopascal),
+            "inhg" => Ok(Self::InchOfMercury),
+            "mmhg" => Ok(Self::MillimeterOfMercury),
+            _ => Err(Error::Parse(format!("invalid pressure unit {}", s))),
+        }
+    }
+}
+
+impl PressureUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::Pascal => "Pa",
+            Self::Hectopascal => "hPa",
+            Self::InchOfMercury => "inHg",
+            Self::MillimeterOfMercury => "mmHg",
+        }
+    }
+
+    /// Convert from pascals.
+    pub fn convert(&self, pascals: f64) -> f64 {
+        match self {
+            Self::Pascal => pascals,
+            Self::Hectopascal => pascals / 100.0,
+            Self::InchOfMercury => pascals / PA_PER_INHG,
+            Self::MillimeterOfMercury => pascals / PA_PER_MMHG,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum AccelerationUnit {
+    #[default]
+    StandardGravity,
+    MetersPerSecondSquared,
+}
+
+impl FromStr for AccelerationUnit {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Res<Self> {
+        match s {
+            "g" => Ok(Self::StandardGravity),
+            "m/s2" => Ok(Self::MetersPerSecondSquared),
+            _ => Err(Error::Parse(format!("invalid acceleration unit {}", s))),
+        }
+    }
+}
+
+impl AccelerationUnit {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            Self::StandardGravity => "g",
+            Self::MetersPerSecondSquared => "m/s²",
+        }
+    }
+
+    /// Convert from standard gravities.
+    pub fn convert(&self, g: f64) -> f64 {
+        match self {
+            Self::StandardGravity => g,
+            Self::MetersPerSecondSquared => g * STANDARD_GRAVITY,
+        }
+    }
+}
+
+/// Units of the measurements in the output. The values themselves are always
+/// in °C, Pa and g, the conversion happens when they are serialized with
+/// [`Units::to_json`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Units {
+    pub temperature: TemperatureUnit,
+    pub air_pressure: PressureUnit,
+    pub acceleration: AccelerationUnit,
+}
+
+impl Units {
+    /// Whether the units are °C, Pa and g.
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Serialize `value` (eg. [`Observation`](crate::Observation),
+    /// [`TaggedRecord`](crate::TaggedRecord) or [`Summary`](crate::Summary)) as
+    /// json with the measurements converted to these units.
+    ///
+    /// With other than the default units, the units are listed in the `units`
+    /// field, eg. `"units":{"temperature":"°F","air_pressure":"hPa",...}`.
+    pub fn to_json(&self, value: &impl Serialize) -> String {
+        // to_value really should not fail...
+        let mut value = serde_json::to_value(value).unwrap();
+        if let Value::Object(fields) = &mut value {
+            if !self.is_default() {
+                self.convert(fields);
+                let units = json!({
+                    "temperature": self.temperature.symbol(),
+                    "air_pressure": self.air_pressure.symbol(),
+                    "acceleration": self.acceleration.symbol(),
+                });
+                fields.insert(String::from("units"), units);
+            }
+        }
+        value.to_string()
+    }
+
+    fn convert(&self, fields: &mut Map<String, Value>) {
+        for (name, value) in fields.iter_mut() {
+            let convert: fn(&Self, f64) -> f64 = match name.as_str() {
+                "temperature" | "temperature_min" | "temperature_max" => {
+                    |u, v| u.temperature.convert(v)
+                }
+                "air_pressure" | "air_pressure_min" | "air_pressure_max" => {
+                    |u, v| u.air_pressure.convert(v)
+                }
+                "acceleration" => |u, v| u.acceleration.convert(v),
+                _ => continue,
+            };
+            match value {
+                Value::Array(values) => values
+                    .iter_mut()
+                    .for_each(|v| convert_value(v, self, convert)),
+                v => convert_value(v, self, convert),
+            }
+        }
+    }
+}
+
+// values in the default units are kept as is (eg. air pressure as an integer)
+fn convert_value(value: &mut Value, units: &Units, convert: fn(&Units, f64) -> f64) {
+    if let Some(v) = value.as_f64() {
+        let converted = convert(units, v);
+        if converted != v {
+            *value = json!(converted);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ruuvi::{test_advertisement, test_observation, Advertisement};
+
+    #[test]
+    fn conversions() {
+        assert_eq!(TemperatureUnit::Fahrenheit.convert(-40.0), -40.0);
+        assert_eq!(TemperatureUnit::Fahrenheit.convert(100.0), 212.0);
+        assert_eq!(TemperatureUnit::Kelvin.convert(0.0), 273.15);
+        assert!((PressureUnit::InchOfMercury.convert(101325.0) - 29.921).abs() < 1e-3);
+        assert!((PressureUnit::MillimeterOfMercury.convert(101325.0) - 760.0).abs() < 1e-3);
+        assert_eq!(
+            AccelerationUnit::MetersPerSecondSquared.convert(2.0),
+            19.6133
+        );
+        assert!("celsius".parse::<TemperatureUnit>().is_err());
+    }
+
+    #[test]
+    fn json() {
+        let adv = Advertisement {
+            air_pressure: 101325,
+            ..test_advertisement([1; 6], 1, 20.0)
+        };
+        let obs = test_observation(0, adv);
+        assert_eq!(Units::default().to_json(&obs), obs.to_string());
+        let units = Units {
+            temperature: TemperatureUnit::Fahrenheit,
+            air_pressure: PressureUnit::Hectopascal,
+            acceleration: AccelerationUnit::StandardGravity,
+        };
+        let value: Value = serde_json::from_str(&units.to_json(&obs)).unwrap();
+        assert_eq!(value["temperature"], json!(68.0));
+        assert_eq!(value["humidity"], json!(40.0));
+        assert_eq!(value["air_pressure"], json!(1013.25));
+        assert_eq!(value["acceleration"], json!([0.0, 0.0, 1.0]));
+        assert_eq!(value["units"]["acceleration"], json!("g"));
+        assert_eq!(value["units"]["temperature"], json!("°F"));
+        assert_eq!(value["units"]["air_pressure"], json!("hPa"));
+    }
+}